@@ -0,0 +1,373 @@
+//! Colour type shared by patterns and every frame sink, and the colour maths
+//! patterns build on.
+//!
+//! Kept free of macroquad so patterns can be run without the windowing stack.
+//! Layout and constructors mirror `macroquad::color::Color`, the viewer converts
+//! between the two.
+//!
+//! Channels are sRGB as sent to the LEDs. Anything that should look even to
+//! the eye (gradients, fades between colours) goes through [`Oklab`] instead of
+//! mixing channels directly, which dips through muddy greys.
+
+/// RGBA colour with each channel in 0.0-1.0.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color { r, g, b, a }
+    }
+
+    /// Builds a colour from 0-255 channel values.
+    pub fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color::new(
+            r as f32 / 255.,
+            g as f32 / 255.,
+            b as f32 / 255.,
+            a as f32 / 255.,
+        )
+    }
+
+    /// Builds an opaque colour from hue in degrees (any value, it wraps),
+    /// saturation and value in 0.0-1.0.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let chroma = value * saturation;
+        let (r, g, b) = hue_to_rgb(hue, chroma);
+        let m = value - chroma;
+        Color::new(r + m, g + m, b + m, 1.)
+    }
+
+    /// Builds an opaque colour from hue in degrees, saturation and lightness
+    /// in 0.0-1.0.
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+        let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+        let (r, g, b) = hue_to_rgb(hue, chroma);
+        let m = lightness - chroma / 2.;
+        Color::new(r + m, g + m, b + m, 1.)
+    }
+
+    /// Each channel clamped to 0.0-1.0.
+    pub fn clamped(self) -> Self {
+        Color::new(
+            self.r.clamp(0., 1.),
+            self.g.clamp(0., 1.),
+            self.b.clamp(0., 1.),
+            self.a.clamp(0., 1.),
+        )
+    }
+
+    /// Dims (or brightens) by `factor`, leaving alpha alone.
+    pub fn scale(self, factor: f32) -> Self {
+        Color::new(self.r * factor, self.g * factor, self.b * factor, self.a)
+    }
+
+    /// Straight mix of the channels, `t` from 0.0 (`self`) to 1.0 (`other`).
+    pub fn lerp(self, other: Color, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Color::new(
+            lerp(self.r, other.r),
+            lerp(self.g, other.g),
+            lerp(self.b, other.b),
+            lerp(self.a, other.a),
+        )
+    }
+}
+
+/// Converts a 0.0-1.0 colour channel into a clamped, rounded 0-255 integer.
+pub fn channel_to_u8(channel: f32) -> u8 {
+    (channel.clamp(0., 1.) * 255.).round() as u8
+}
+
+/// The hue part of HSV and HSL, RGB with the smallest channel at 0 and the
+/// largest at `chroma`.
+fn hue_to_rgb(hue: f32, chroma: f32) -> (f32, f32, f32) {
+    let sector = hue.rem_euclid(360.) / 60.;
+    let x = chroma * (1. - (sector % 2. - 1.).abs());
+    match sector as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    }
+}
+
+/// A colour in the OKLab perceptual colour space.
+///
+/// `l` is lightness, 0.0 black to 1.0 white. `a` runs green to red and `b`
+/// blue to yellow, both roughly -0.4 to 0.4. Equal steps in here look like
+/// equal steps to the eye, so it's the space to fade and compare colours in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Oklab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Oklab {
+    /// From lightness, chroma (colourfulness, 0.0 to about 0.3) and hue in
+    /// degrees, OKLab's polar form.
+    pub fn from_lch(lightness: f32, chroma: f32, hue: f32) -> Self {
+        let hue = hue.to_radians();
+        Oklab {
+            l: lightness,
+            a: chroma * hue.cos(),
+            b: chroma * hue.sin(),
+        }
+    }
+
+    pub fn lerp(self, other: Oklab, t: f32) -> Self {
+        Oklab {
+            l: self.l + (other.l - self.l) * t,
+            a: self.a + (other.a - self.a) * t,
+            b: self.b + (other.b - self.b) * t,
+        }
+    }
+
+    /// Back to an opaque sRGB colour, clamped to what can be shown.
+    pub fn to_color(self) -> Color {
+        // https://bottosson.github.io/posts/oklab/
+        let l = self.l + 0.396_337_78 * self.a + 0.215_803_76 * self.b;
+        let m = self.l - 0.105_561_346 * self.a - 0.063_854_17 * self.b;
+        let s = self.l - 0.089_484_18 * self.a - 1.291_485_5 * self.b;
+        let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+
+        let r = 4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s;
+        let g = -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s;
+        let b = -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s;
+        Color::new(linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), 1.).clamped()
+    }
+}
+
+impl From<Color> for Oklab {
+    fn from(color: Color) -> Self {
+        let r = srgb_to_linear(color.r);
+        let g = srgb_to_linear(color.g);
+        let b = srgb_to_linear(color.b);
+
+        let l = 0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b;
+        let m = 0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b;
+        let s = 0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b;
+        let (l, m, s) = (l.cbrt(), m.cbrt(), s.cbrt());
+
+        Oklab {
+            l: 0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+            a: 1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+            b: 0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+        }
+    }
+}
+
+/// sRGB channel to linear light.
+fn srgb_to_linear(channel: f32) -> f32 {
+    let channel = channel.clamp(0., 1.);
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light back to an sRGB channel.
+fn linear_to_srgb(channel: f32) -> f32 {
+    let channel = channel.max(0.);
+    if channel <= 0.003_130_8 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Smooth run of colours, faded between perceptually.
+#[derive(Clone, Debug)]
+pub struct Gradient {
+    stops: Vec<Oklab>,
+}
+
+impl Gradient {
+    /// Evenly spaced stops, at least one.
+    pub fn new(colors: &[Color]) -> Self {
+        assert!(!colors.is_empty(), "gradient needs at least one colour");
+        Gradient {
+            stops: colors.iter().map(|&color| Oklab::from(color)).collect(),
+        }
+    }
+
+    /// Colour `t` of the way along, fading through every stop and from the
+    /// last back round to the first, repeating every 1.0.
+    pub fn sample_looped(&self, t: f32) -> Color {
+        let t = t.rem_euclid(1.) * self.stops.len() as f32;
+        let i = (t as usize).min(self.stops.len() - 1);
+        let next = (i + 1) % self.stops.len();
+        self.stops[i]
+            .lerp(self.stops[next], t - i as f32)
+            .to_color()
+    }
+}
+
+/// Red, green and gold, with a little white.
+pub const CHRISTMAS: &[Color] = &[
+    Color::new(0.8, 0.05, 0.05, 1.),
+    Color::new(0.05, 0.55, 0.15, 1.),
+    Color::new(1., 0.7, 0.15, 1.),
+    Color::new(0.95, 0.95, 0.9, 1.),
+];
+
+/// Red and white stripes.
+pub const CANDY_CANE: &[Color] = &[Color::new(0.9, 0.05, 0.1, 1.), Color::new(1., 1., 1., 1.)];
+
+/// The five colours of old C9 bulb strings.
+pub const TRADITIONAL: &[Color] = &[
+    Color::new(1., 0.05, 0.05, 1.),
+    Color::new(1., 0.5, 0., 1.),
+    Color::new(0.05, 0.8, 0.1, 1.),
+    Color::new(0.1, 0.2, 1., 1.),
+    Color::new(1., 0.85, 0.1, 1.),
+];
+
+/// Frosty blues and white.
+pub const ICY: &[Color] = &[
+    Color::new(0.6, 0.85, 1., 1.),
+    Color::new(0.1, 0.35, 0.9, 1.),
+    Color::new(0.95, 0.98, 1., 1.),
+];
+
+/// Candle and warm white tones.
+pub const WARM_WHITE: &[Color] = &[
+    Color::new(1., 0.6, 0.2, 1.),
+    Color::new(1., 0.8, 0.5, 1.),
+    Color::new(1., 0.9, 0.75, 1.),
+];
+
+/// Every named palette, for patterns that let one be picked by number.
+pub const PALETTES: &[(&str, &[Color])] = &[
+    ("christmas", CHRISTMAS),
+    ("candy_cane", CANDY_CANE),
+    ("traditional", TRADITIONAL),
+    ("icy", ICY),
+    ("warm_white", WARM_WHITE),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Color, b: Color) -> bool {
+        (a.r - b.r).abs() < 1e-5 && (a.g - b.g).abs() < 1e-5 && (a.b - b.b).abs() < 1e-5
+    }
+
+    /// Close enough for anything that's been through OKLab and back.
+    fn roughly(a: Color, b: Color) -> bool {
+        (a.r - b.r).abs() < 1e-3 && (a.g - b.g).abs() < 1e-3 && (a.b - b.b).abs() < 1e-3
+    }
+
+    #[test]
+    fn channel_clamping() {
+        assert_eq!(channel_to_u8(-1.), 0);
+        assert_eq!(channel_to_u8(0.), 0);
+        assert_eq!(channel_to_u8(0.5019608), 128);
+        assert_eq!(channel_to_u8(1.), 255);
+        assert_eq!(channel_to_u8(2.), 255);
+    }
+
+    #[test]
+    fn hsv_and_hsl() {
+        for (hue, rgb) in [
+            (0., (1., 0., 0.)),
+            (60., (1., 1., 0.)),
+            (120., (0., 1., 0.)),
+            (180., (0., 1., 1.)),
+            (240., (0., 0., 1.)),
+            (300., (1., 0., 1.)),
+            (360., (1., 0., 0.)),
+            (-120., (0., 0., 1.)),
+        ] {
+            let expected = Color::new(rgb.0, rgb.1, rgb.2, 1.);
+            assert!(close(Color::from_hsv(hue, 1., 1.), expected), "{}", hue);
+            assert!(close(Color::from_hsl(hue, 1., 0.5), expected), "{}", hue);
+        }
+
+        assert!(close(
+            Color::from_hsv(30., 0.5, 0.8),
+            Color::new(0.8, 0.6, 0.4, 1.)
+        ));
+        assert!(close(
+            Color::from_hsl(30., 0.5, 0.6),
+            Color::new(0.8, 0.6, 0.4, 1.)
+        ));
+        assert!(close(
+            Color::from_hsl(200., 1., 1.),
+            Color::new(1., 1., 1., 1.)
+        ));
+    }
+
+    #[test]
+    fn oklab() {
+        // Reference values from the OKLab post
+        let white = Oklab::from(Color::new(1., 1., 1., 1.));
+        assert!((white.l - 1.).abs() < 1e-3 && white.a.abs() < 1e-3 && white.b.abs() < 1e-3);
+        let red = Oklab::from(Color::new(1., 0., 0., 1.));
+        assert!((red.l - 0.628).abs() < 1e-3);
+        assert!((red.a - 0.225).abs() < 1e-3);
+        assert!((red.b - 0.126).abs() < 1e-3);
+
+        for color in [
+            Color::new(0.8, 0.6, 0.4, 1.),
+            Color::new(0., 0., 0., 1.),
+            Color::new(0.05, 0.55, 0.15, 1.),
+        ] {
+            assert!(roughly(Oklab::from(color).to_color(), color), "{:?}", color);
+        }
+
+        // Out of gamut comes back clamped
+        let vivid = Oklab::from_lch(0.7, 0.4, 150.).to_color();
+        assert!([vivid.r, vivid.g, vivid.b]
+            .iter()
+            .all(|c| (0. ..=1.).contains(c)));
+    }
+
+    #[test]
+    fn mixing() {
+        let red = Color::new(1., 0., 0., 1.);
+        let green = Color::new(0., 1., 0., 1.);
+        assert!(close(red.lerp(green, 0.5), Color::new(0.5, 0.5, 0., 1.)));
+
+        // Mixing in OKLab keeps the middle brighter than a straight mix
+        let mixed = Oklab::from(red).lerp(Oklab::from(green), 0.5);
+        assert!(mixed.l > Oklab::from(red.lerp(green, 0.5)).l);
+
+        let grey = Color::new(0.5, 0.5, 0.5, 1.);
+        assert!(close(grey.scale(0.5), Color::new(0.25, 0.25, 0.25, 1.)));
+        assert!(close(
+            Color::new(1.5, -0.5, 0.5, 2.).clamped(),
+            Color::new(1., 0., 0.5, 1.)
+        ));
+    }
+
+    #[test]
+    fn gradients() {
+        let black = Color::new(0., 0., 0., 1.);
+        let white = Color::new(1., 1., 1., 1.);
+        let grey = Oklab::from(black).lerp(Oklab::from(white), 0.5).to_color();
+
+        let looped = Gradient::new(&[black, white]);
+        assert!(roughly(looped.sample_looped(0.), black));
+        assert!(roughly(looped.sample_looped(0.5), white));
+        assert!(roughly(looped.sample_looped(1.25), grey));
+        assert!(roughly(looped.sample_looped(-0.25), grey));
+        assert!(roughly(looped.sample_looped(0.999_99), black));
+
+        let single = Gradient::new(&[white]);
+        assert!(roughly(single.sample_looped(0.3), white));
+
+        for (name, palette) in PALETTES {
+            assert!(!palette.is_empty(), "{}", name);
+        }
+    }
+}
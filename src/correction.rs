@@ -0,0 +1,282 @@
+//! Colour correction between a pattern and the LEDs it ends up on.
+//!
+//! Patterns are written to look right on screen, physical strings need some
+//! help to match: a gamma curve so fades look even, a brightness cap,
+//! per-channel white balance, and channels in whatever order the string
+//! wants them. Each output (and export) gets its own, the screen never needs
+//! one.
+//!
+//! Applied in that order too. The curve works on the pattern's colours, the
+//! brightness and balance then scale what's actually driven.
+//!
+//! Given as `key=value` options: `order=grb;gamma=2.8;brightness=0.5;balance=1,0.9,0.7`,
+//! or `lut=<file>` in place of `gamma` for a 256 entry lookup table.
+
+use std::fs;
+use std::str::FromStr;
+
+use crate::colors::{channel_to_u8, Color};
+use crate::output::Options;
+
+/// Order a string's controller expects each pixel's channels in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelOrder([usize; 3]);
+
+impl ChannelOrder {
+    /// Moves the channels of `color` into this order, so its `r` is whatever
+    /// should go out first and so on.
+    pub fn apply(self, color: Color) -> Color {
+        let rgb = [color.r, color.g, color.b];
+        let [a, b, c] = self.0;
+        Color::new(rgb[a], rgb[b], rgb[c], color.a)
+    }
+}
+
+impl Default for ChannelOrder {
+    fn default() -> Self {
+        ChannelOrder([0, 1, 2])
+    }
+}
+
+impl FromStr for ChannelOrder {
+    type Err = String;
+
+    /// Any arrangement of `r`, `g` and `b`, like `grb`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut order = [0; 3];
+        let mut seen = [false; 3];
+        let mut chars = s.chars();
+        for slot in order.iter_mut() {
+            let channel = match chars.next().map(|c| c.to_ascii_lowercase()) {
+                Some('r') => 0,
+                Some('g') => 1,
+                Some('b') => 2,
+                _ => return Err(format!("channel order \"{}\" isn't like rgb or grb", s)),
+            };
+            if seen[channel] {
+                return Err(format!("channel order \"{}\" repeats a channel", s));
+            }
+            seen[channel] = true;
+            *slot = channel;
+        }
+        if chars.next().is_some() {
+            return Err(format!("channel order \"{}\" isn't like rgb or grb", s));
+        }
+        Ok(ChannelOrder(order))
+    }
+}
+
+/// Response curve applied to each channel.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// Raised to the power of, 2.2-2.8 suits most LEDs.
+    Gamma(f32),
+    /// Output for each 8-bit input.
+    Lut(Vec<u8>),
+}
+
+impl Curve {
+    fn apply(&self, channel: f32) -> f32 {
+        match self {
+            Curve::Linear => channel,
+            Curve::Gamma(gamma) => channel.clamp(0., 1.).powf(*gamma),
+            Curve::Lut(lut) => lut[channel_to_u8(channel) as usize] as f32 / 255.,
+        }
+    }
+}
+
+/// Parses a lookup table, 256 values of 0-255 separated by commas or whitespace.
+pub fn parse_lut(text: &str) -> Result<Vec<u8>, String> {
+    let lut = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("LUT value \"{}\" isn't 0-255", value))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+
+    if lut.len() != 256 {
+        return Err(format!("LUT has {} values, needs 256", lut.len()));
+    }
+    Ok(lut)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correction {
+    pub order: ChannelOrder,
+    pub curve: Curve,
+    /// 0.0-1.0, scales everything.
+    pub brightness: f32,
+    /// 0.0-1.0 scale for each of red, green and blue.
+    pub white_balance: [f32; 3],
+}
+
+impl Default for Correction {
+    /// Leaves colours alone.
+    fn default() -> Self {
+        Correction {
+            order: ChannelOrder::default(),
+            curve: Curve::Linear,
+            brightness: 1.,
+            white_balance: [1., 1., 1.],
+        }
+    }
+}
+
+impl Correction {
+    pub fn is_identity(&self) -> bool {
+        *self == Correction::default()
+    }
+
+    pub fn apply(&self, color: Color) -> Color {
+        let [r, g, b] = self.white_balance;
+        let corrected = Color::new(
+            self.curve.apply(color.r) * self.brightness * r,
+            self.curve.apply(color.g) * self.brightness * g,
+            self.curve.apply(color.b) * self.brightness * b,
+            color.a,
+        );
+        self.order.apply(corrected)
+    }
+
+    /// Corrects a whole frame in place.
+    pub fn apply_frame(&self, frame: &mut [Color]) {
+        if self.is_identity() {
+            return;
+        }
+        for color in frame.iter_mut() {
+            *color = self.apply(*color);
+        }
+    }
+
+    /// Takes the correction options out of `options`, leaving anything else.
+    pub fn from_options(options: &mut Options) -> Result<Self, String> {
+        let curve = match (options.take_str("gamma"), options.take_str("lut")) {
+            (Some(_), Some(_)) => return Err(String::from("give either gamma or lut, not both")),
+            (Some(gamma), None) => match gamma.parse::<f32>() {
+                Ok(gamma) if gamma > 0. && gamma <= 10. => Curve::Gamma(gamma),
+                _ => return Err(format!("gamma \"{}\" isn't within 0-10", gamma)),
+            },
+            (None, Some(path)) => {
+                let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+                Curve::Lut(parse_lut(&text).map_err(|err| format!("{}: {}", path, err))?)
+            }
+            (None, None) => Curve::Linear,
+        };
+
+        let brightness = options.take("brightness", 1.)?;
+        if !(0. ..=1.).contains(&brightness) {
+            return Err(format!("brightness {} isn't within 0-1", brightness));
+        }
+
+        let white_balance = match options.take_str("balance") {
+            Some(balance) => {
+                let bad = || {
+                    format!(
+                        "balance \"{}\" isn't three 0-1 values like 1,0.9,0.8",
+                        balance
+                    )
+                };
+                let fields: Vec<&str> = balance.split(',').collect();
+                if fields.len() != 3 {
+                    return Err(bad());
+                }
+                let mut values = [0.; 3];
+                for (value, field) in values.iter_mut().zip(fields) {
+                    *value = match field.trim().parse::<f32>() {
+                        Ok(v) if (0. ..=1.).contains(&v) => v,
+                        _ => return Err(bad()),
+                    };
+                }
+                values
+            }
+            None => [1., 1., 1.],
+        };
+
+        Ok(Correction {
+            order: options.take("order", ChannelOrder::default())?,
+            curve,
+            brightness,
+            white_balance,
+        })
+    }
+}
+
+impl FromStr for Correction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = Options::parse(s.split(';'))?;
+        let correction = Correction::from_options(&mut options)?;
+        options.finish("colour correction")?;
+        Ok(correction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Color, b: Color) -> bool {
+        (a.r - b.r).abs() < 1e-5 && (a.g - b.g).abs() < 1e-5 && (a.b - b.b).abs() < 1e-5
+    }
+
+    #[test]
+    fn identity() {
+        let correction: Correction = "".parse().unwrap();
+        assert!(correction.is_identity());
+
+        let mut frame = vec![Color::new(0.1, 0.2, 0.3, 1.)];
+        correction.apply_frame(&mut frame);
+        assert_eq!(frame[0], Color::new(0.1, 0.2, 0.3, 1.));
+    }
+
+    #[test]
+    fn pipeline() {
+        let correction: Correction = "order=grb; gamma=2; brightness=0.5; balance=1,0.5,1"
+            .parse()
+            .unwrap();
+        let corrected = correction.apply(Color::new(0.5, 1., 0., 1.));
+        // Squared, halved, green halved again, then green goes first
+        assert!(close(corrected, Color::new(0.25, 0.125, 0., 1.)));
+    }
+
+    #[test]
+    fn lookup_table() {
+        let mut text: Vec<String> = (0..256).map(|i| (255 - i).to_string()).collect();
+        let lut = parse_lut(&text.join(", ")).unwrap();
+        let correction = Correction {
+            curve: Curve::Lut(lut),
+            ..Default::default()
+        };
+        assert!(close(
+            correction.apply(Color::new(0., 1., 0.2, 1.)),
+            Color::new(1., 0., 204. / 255., 1.)
+        ));
+
+        text.pop();
+        assert!(parse_lut(&text.join(" ")).is_err());
+        text.push(String::from("256"));
+        assert!(parse_lut(&text.join("\n")).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        for bad in [
+            "order=rgg",
+            "gamma=0",
+            "gamma=2;lut=x",
+            "brightness=1.5",
+            "balance=1,1",
+            "balance=1,1,2",
+            "balance=1,abc,1,1",
+            "balance=2,1,1,1",
+            "hue=3",
+        ] {
+            assert!(bad.parse::<Correction>().is_err(), "{}", bad);
+        }
+    }
+}
@@ -2,44 +2,424 @@
 //!
 //! Frame data is exported as a CSV in the following form:
 //! FRAME_ID, R_0, G_0, B_0, R_1, G_1, B_1, ...
+//!
+//! By default colour channels are written as 0-255 integers, which is what the
+//! xmastree2021 `run-a-file.py` runner expects. Raw 0.0-1.0 floats can be
+//! written instead for debugging.
+//!
+//! Frames can also be exported as xLights/FPP `.fseq` sequences, see
+//! [`crate::fseq`].
+//!
+//! Frames are written out as they're generated, so exports of any length run
+//! in constant memory and an interrupted export keeps what it got through.
 
-use crate::patterns::Pattern;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Seek, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::colors::{channel_to_u8, Color};
+use crate::correction::Correction;
+use crate::fseq::{self, Compression, FseqWriter};
+use crate::patterns::{FrameContext, Pattern, PatternArgs};
+use crate::power::{PowerModel, PowerStats};
 use crate::tree::Pixel;
 
-pub fn export_pattern<T: Pattern>(
-    tree: &Vec<Pixel>,
-    pattern: &mut T,
-    frame_limit: usize,
+/// Which pattern an export came from and how it was seeded, enough to get the
+/// same frames out of it again. Output settings like correction, power limits
+/// and format aren't kept, they have to be given again to match the file.
+pub struct ExportInfo<'a> {
+    pub pattern: &'a str,
+    pub args: &'a PatternArgs,
+    pub seed: u64,
+    pub fps: u32,
+}
+
+impl ExportInfo<'_> {
+    /// The info as `key=value` lines.
+    pub fn metadata(&self) -> String {
+        format!(
+            "pattern={}\npattern-args={}\nseed={}\nfps={}\n",
+            self.pattern, self.args, self.seed, self.fps
+        )
+    }
+
+    /// Writes the metadata to a `.meta` file next to the export.
+    ///
+    /// The CSV itself has no room for metadata without confusing other tools.
+    pub fn write_metadata(&self, filename: &str) -> io::Result<()> {
+        std::fs::write(format!("{}.meta", filename), self.metadata())
+    }
+}
+
+/// Reports how far along an export is on stderr, when it's a terminal.
+pub struct Progress {
+    /// What's being done to the frames, e.g. "exported".
+    verb: &'static str,
+    enabled: bool,
+    total: usize,
+    last_report: Instant,
+}
+
+impl Progress {
+    pub fn new(verb: &'static str, total: usize) -> Self {
+        Progress {
+            verb,
+            enabled: io::stderr().is_terminal(),
+            total,
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn update(&mut self, frames: usize) {
+        // Only a few times a second, stderr isn't free either
+        if self.enabled && self.last_report.elapsed() >= Duration::from_millis(250) {
+            self.last_report = Instant::now();
+            eprint!("\r{} {}/{} frames", self.verb, frames, self.total);
+        }
+    }
+
+    pub fn finish(&self, frames: usize) {
+        if self.enabled {
+            eprintln!("\r{} {}/{} frames", self.verb, frames, self.total);
+        }
+    }
+}
+
+/// File format to export to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Matt Parker's xmastree2021 CSV.
+    Csv,
+    /// xLights/FPP sequence.
+    Fseq,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "fseq" => Ok(Format::Fseq),
+            _ => Err(format!("unknown format \"{}\", expected csv or fseq", s)),
+        }
+    }
+}
+
+/// Everything about how to export, apart from what.
+pub struct ExportOptions {
+    pub format: Format,
+    /// Maximum number of frames to export.
+    pub frame_limit: usize,
+    pub fps: u32,
+    /// CSV only, write raw 0.0-1.0 floats instead of 0-255 integers.
+    pub float: bool,
+    /// FSEQ only.
+    pub compression: Compression,
+    pub correction: Correction,
+    /// Frames are limited after correction, so it's what the LEDs really get.
+    pub power: PowerModel,
+}
+
+/// Exports `pattern` to `filename`, or stdout if it's `-`.
+pub fn export_pattern(
+    tree: &[Pixel],
+    pattern: &mut dyn Pattern,
     filename: &str,
-) -> std::io::Result<()> {
-    let mut out = String::new();
+    options: &ExportOptions,
+) -> io::Result<()> {
+    // Time would stand still at infinite steps between frames
+    if options.fps == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "fps must be at least 1",
+        ));
+    }
+
+    match (options.format, filename) {
+        (Format::Csv, "-") => {
+            let stdout = io::stdout();
+            let out = BufWriter::new(stdout.lock());
+            write_csv(out, tree.len(), pattern, options)
+        }
+        (Format::Csv, _) => {
+            let out = BufWriter::new(File::create(filename)?);
+            write_csv(out, tree.len(), pattern, options)
+        }
+        (Format::Fseq, "-") => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "fseq files can't be written to stdout",
+        )),
+        (Format::Fseq, _) => {
+            let out = BufWriter::new(File::create(filename)?);
+            write_fseq(out, tree.len(), pattern, options)
+        }
+    }
+}
+
+/// Runs `pattern` for up to the frame limit, handing each frame to `write` as
+/// soon as it's generated and corrected. Reports the power drawn at the end.
+fn for_each_frame<F>(
+    pattern: &mut dyn Pattern,
+    options: &ExportOptions,
+    mut write: F,
+) -> io::Result<()>
+where
+    F: FnMut(usize, Vec<Color>) -> io::Result<()>,
+{
+    let mut progress = Progress::new("exported", options.frame_limit);
+    let mut stats = PowerStats::default();
+    let mut i = 0;
+    while i < options.frame_limit {
+        let mut frame = match pattern.next_frame(&FrameContext::at_rate(i, options.fps)) {
+            Some(frame) => frame,
+            None => break,
+        };
+        options.correction.apply_frame(&mut frame);
+        stats.add(options.power.limit(&mut frame));
+        write(i, frame)?;
 
+        i += 1;
+        progress.update(i);
+    }
+
+    progress.finish(i);
+    eprintln!("{}", stats);
+    Ok(())
+}
+
+/// Streams frames out as CSV.
+pub fn write_csv<W: Write>(
+    mut out: W,
+    num_pixels: usize,
+    pattern: &mut dyn Pattern,
+    options: &ExportOptions,
+) -> io::Result<()> {
     // Push out header
-    let num_pixels = tree.len();
-    out.push_str("FRAME_ID");
+    write!(out, "FRAME_ID")?;
     for i in 0..num_pixels {
-        out.push_str(format!(",R_{},G_{},B_{}", i, i, i).as_str());
+        write!(out, ",R_{},G_{},B_{}", i, i, i)?;
     }
-    out.push_str("\n");
+    writeln!(out)?;
 
-    let mut i = 0;
-    while let Some(frame) = pattern.next_frame() {
+    for_each_frame(pattern, options, |i, frame| {
         // Frame index
-        out.push_str(format!("{}", i).as_str());
+        write!(out, "{}", i)?;
 
         // Write out RGB value per pixel
         for pixel in frame {
-            out.push_str(format!(",{},{},{}", pixel.r, pixel.g, pixel.b).as_str());
+            if options.float {
+                // Debug formatting keeps the decimal point on whole numbers,
+                // which is how playback tells floats from 0-255
+                write!(out, ",{:?},{:?},{:?}", pixel.r, pixel.g, pixel.b)?;
+            } else {
+                write!(
+                    out,
+                    ",{},{},{}",
+                    channel_to_u8(pixel.r),
+                    channel_to_u8(pixel.g),
+                    channel_to_u8(pixel.b)
+                )?;
+            }
         }
+        writeln!(out)
+    })?;
 
-        out.push_str("\n");
+    out.flush()
+}
 
-        i += 1;
-        if i >= frame_limit {
-            break;
+/// Writes frames out as an FSEQ sequence, one RGB channel triple per pixel.
+pub fn write_fseq<W: Write + Seek>(
+    out: W,
+    num_pixels: usize,
+    pattern: &mut dyn Pattern,
+    options: &ExportOptions,
+) -> io::Result<()> {
+    let step_ms = match fseq::step_time_ms(options.fps) {
+        Some(step_ms) => step_ms,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("fseq can't store a frame rate of {} fps", options.fps),
+            ))
         }
-    }
+    };
+
+    let channels = num_pixels * 3;
+    let mut writer = FseqWriter::new(
+        out,
+        channels,
+        step_ms,
+        options.compression,
+        options.frame_limit,
+    )?;
 
-    std::fs::write(filename, out)?;
+    let mut data = Vec::with_capacity(channels);
+    for_each_frame(pattern, options, |_, frame| {
+        data.clear();
+        for pixel in frame.iter().take(num_pixels) {
+            data.push(channel_to_u8(pixel.r));
+            data.push(channel_to_u8(pixel.g));
+            data.push(channel_to_u8(pixel.b));
+        }
+        // Short frames are padded out black rather than corrupting the file
+        data.resize(channels, 0);
+        writer.write_frame(&data)
+    })?;
+
+    writer.finish()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::vec3;
+
+    /// Plays back a fixed list of frames.
+    struct Frames(Vec<Vec<Color>>);
+
+    impl Pattern for Frames {
+        fn next_frame(&mut self, _ctx: &FrameContext) -> Option<Vec<Color>> {
+            if self.0.is_empty() {
+                None
+            } else {
+                Some(self.0.remove(0))
+            }
+        }
+    }
+
+    fn test_frames() -> Vec<Vec<Color>> {
+        vec![
+            vec![Color::new(0., 0.5, 1., 1.), Color::new(1.5, -0.2, 0.25, 1.)],
+            vec![Color::new(0.1, 0.9, 0.002, 1.), Color::new(1., 1., 1., 1.)],
+            vec![
+                Color::new(0., 0., 0., 1.),
+                Color::new(0.5019608, 0.2, 0.8, 1.),
+            ],
+        ]
+    }
+
+    fn export_to_string(frames: Vec<Vec<Color>>, float: bool, name: &str) -> String {
+        let tree = vec![vec3(0., 0., 0.), vec3(0., 0., 1.)];
+        let path =
+            std::env::temp_dir().join(format!("xmas-mapper-{}-{}.csv", name, std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let options = ExportOptions {
+            format: Format::Csv,
+            frame_limit: 100,
+            fps: 30,
+            float,
+            compression: Compression::None,
+            correction: Default::default(),
+            power: Default::default(),
+        };
+        export_pattern(&tree, &mut Frames(frames), path, &options).unwrap();
+        let out = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        out
+    }
+
+    #[test]
+    fn integer_round_trip() {
+        let frames = test_frames();
+        let out = export_to_string(frames.clone(), false, "int");
+        let mut lines = out.lines();
+
+        assert_eq!(lines.next(), Some("FRAME_ID,R_0,G_0,B_0,R_1,G_1,B_1"));
+        for (i, frame) in frames.iter().enumerate() {
+            let values: Vec<u32> = lines
+                .next()
+                .unwrap()
+                .split(',')
+                .map(|v| v.parse().unwrap())
+                .collect();
+
+            assert_eq!(values[0], i as u32);
+            let expected: Vec<u32> = frame
+                .iter()
+                .flat_map(|c| vec![c.r, c.g, c.b])
+                .map(|c| channel_to_u8(c) as u32)
+                .collect();
+            assert_eq!(&values[1..], expected.as_slice());
+            assert!(values[1..].iter().all(|v| *v <= 255));
+        }
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn csv_to_writer() {
+        let options = ExportOptions {
+            format: Format::Csv,
+            frame_limit: 2,
+            fps: 30,
+            float: false,
+            compression: Compression::None,
+            correction: Default::default(),
+            power: Default::default(),
+        };
+        let mut out = Vec::new();
+        write_csv(&mut out, 2, &mut Frames(test_frames()), &options).unwrap();
+
+        // Stops at the frame limit, with one frame still to go
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "FRAME_ID,R_0,G_0,B_0,R_1,G_1,B_1\n\
+             0,0,128,255,255,0,64\n\
+             1,26,230,1,255,255,255\n"
+        );
+    }
+
+    #[test]
+    fn float_round_trip() {
+        let frames = test_frames();
+        let out = export_to_string(frames.clone(), true, "float");
+        let rows: Vec<Vec<f32>> = out
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').map(|v| v.parse().unwrap()).collect())
+            .collect();
+
+        assert_eq!(rows.len(), frames.len());
+        for (row, frame) in rows.iter().zip(&frames) {
+            let expected: Vec<f32> = frame.iter().flat_map(|c| vec![c.r, c.g, c.b]).collect();
+            assert_eq!(&row[1..], expected.as_slice());
+        }
+
+        // Only full and off, which playback must still read as floats
+        let frames = vec![vec![Color::new(0., 1., 0., 1.), Color::new(0., 0., 0., 1.)]];
+        let out = export_to_string(frames.clone(), true, "float-whole");
+        assert_eq!(crate::playback::parse_csv(&out, 2).unwrap(), frames);
+    }
+
+    #[test]
+    fn fseq_round_trip() {
+        let frames = test_frames();
+        let options = ExportOptions {
+            format: Format::Fseq,
+            frame_limit: 100,
+            fps: 40,
+            float: false,
+            compression: Compression::Zstd,
+            correction: Default::default(),
+            power: Default::default(),
+        };
+        let mut out = io::Cursor::new(Vec::new());
+        write_fseq(&mut out, 2, &mut Frames(frames.clone()), &options).unwrap();
+
+        let sequence = fseq::read(out.into_inner().as_slice()).unwrap();
+        assert_eq!(sequence.channels, 6);
+        assert_eq!(sequence.step_ms, 25);
+        assert_eq!(sequence.frames.len(), frames.len());
+        for (data, frame) in sequence.frames.iter().zip(&frames) {
+            let expected: Vec<u8> = frame
+                .iter()
+                .flat_map(|c| vec![c.r, c.g, c.b])
+                .map(channel_to_u8)
+                .collect();
+            assert_eq!(data, &expected);
+        }
+    }
+}
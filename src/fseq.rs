@@ -0,0 +1,485 @@
+//! xLights/FPP `.fseq` sequence files, version 2.
+//!
+//! Layout, all integers little-endian:
+//! - 32 byte fixed header (`PSEQ`, offsets, channel/frame counts, step time,
+//!   compression)
+//! - compression block index, 8 bytes per block (first frame, compressed size)
+//! - variable headers (`u16` length, 2 char code, data)
+//! - channel data, one byte per channel per frame, either raw or as
+//!   independently zstd-compressed blocks of frames
+//!
+//! Sparse ranges and zlib compression aren't supported.
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+const MAGIC: &[u8; 4] = b"PSEQ";
+const FIXED_HEADER_LEN: usize = 32;
+const MAJOR_VERSION: u8 = 2;
+const MINOR_VERSION: u8 = 0;
+/// Version 2.0 only has a byte for the block count.
+const MAX_BLOCKS: usize = 255;
+/// Least channel data in a compressed block, smaller ones don't compress well.
+const MIN_BLOCK_BYTES: usize = 64 * 1024;
+const PRODUCER: &str = "xmas-mapper";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Zstd,
+}
+
+impl Compression {
+    fn header_value(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "unknown compression \"{}\", expected none or zstd",
+                s
+            )),
+        }
+    }
+}
+
+/// Frame step time for a frame rate, as stored in the header.
+pub fn step_time_ms(fps: u32) -> Option<u8> {
+    let ms = 1000. / fps as f32;
+    if fps == 0 || !(1. ..=255.).contains(&ms) {
+        None
+    } else {
+        Some(ms.round() as u8)
+    }
+}
+
+/// Writes frames of channel data out as an FSEQ file.
+///
+/// Uncompressed data streams straight to `out`. Compressed blocks are kept in
+/// memory until `finish`, since the block index sits in front of them.
+pub struct FseqWriter<W: Write + Seek> {
+    out: W,
+    channels: usize,
+    step_ms: u8,
+    compression: Compression,
+    frames_per_block: usize,
+
+    frames: usize,
+    /// Uncompressed frames of the block being built.
+    block: Vec<u8>,
+    /// First frame and compressed data of each finished block.
+    blocks: Vec<(usize, Vec<u8>)>,
+    /// FNV-1a hash of the channel data, used as the file's unique ID so the
+    /// same animation always gives the same file.
+    hash: u64,
+}
+
+impl<W: Write + Seek> FseqWriter<W> {
+    /// Starts a file of `channels` per frame.
+    ///
+    /// Compressed blocks hold at least 64 KiB, and enough frames that
+    /// `max_frames` fits in 255 of them. Fewer frames can be written, more fail
+    /// once every block is full.
+    pub fn new(
+        mut out: W,
+        channels: usize,
+        step_ms: u8,
+        compression: Compression,
+        max_frames: usize,
+    ) -> io::Result<Self> {
+        // Reserve room for the header, it's filled in on finish
+        if compression == Compression::None {
+            out.write_all(&header(channels, 0, step_ms, compression, &[], 0))?;
+        }
+
+        Ok(FseqWriter {
+            out,
+            channels,
+            step_ms,
+            compression,
+            // Big enough blocks to compress well, unless that's so few there
+            // would be too many of them
+            frames_per_block: MIN_BLOCK_BYTES
+                .div_ceil(channels.max(1))
+                .max(max_frames.div_ceil(MAX_BLOCKS))
+                .max(1),
+            frames: 0,
+            block: Vec::new(),
+            blocks: Vec::new(),
+            hash: 0xcbf2_9ce4_8422_2325,
+        })
+    }
+
+    /// Adds a frame, which must be exactly `channels` long.
+    pub fn write_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        assert_eq!(data.len(), self.channels, "frame has wrong channel count");
+        for byte in data {
+            self.hash = (self.hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+
+        self.frames += 1;
+        match self.compression {
+            Compression::None => self.out.write_all(data),
+            Compression::Zstd => {
+                self.block.extend_from_slice(data);
+                if self.block.len() >= self.frames_per_block * self.channels {
+                    self.finish_block()
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn finish_block(&mut self) -> io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+
+        // The header only has a byte for the block count
+        if self.blocks.len() == MAX_BLOCKS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "more frames than the file was started for",
+            ));
+        }
+
+        let first_frame = self.frames - self.block.len() / self.channels;
+        let compressed = zstd::encode_all(self.block.as_slice(), 0)?;
+        self.blocks.push((first_frame, compressed));
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Fills in the header and writes out any buffered data.
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.compression {
+            Compression::None => {
+                let header = header(
+                    self.channels,
+                    self.frames,
+                    self.step_ms,
+                    self.compression,
+                    &[],
+                    self.hash,
+                );
+                self.out.seek(SeekFrom::Start(0))?;
+                self.out.write_all(&header)?;
+                self.out.seek(SeekFrom::End(0))?;
+            }
+            Compression::Zstd => {
+                // The last block was only partly full
+                self.finish_block()?;
+
+                let index: Vec<(usize, usize)> = self
+                    .blocks
+                    .iter()
+                    .map(|(frame, data)| (*frame, data.len()))
+                    .collect();
+                let header = header(
+                    self.channels,
+                    self.frames,
+                    self.step_ms,
+                    self.compression,
+                    &index,
+                    self.hash,
+                );
+                self.out.write_all(&header)?;
+                for (_, data) in &self.blocks {
+                    self.out.write_all(data)?;
+                }
+            }
+        }
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Builds everything in front of the channel data.
+fn header(
+    channels: usize,
+    frames: usize,
+    step_ms: u8,
+    compression: Compression,
+    blocks: &[(usize, usize)],
+    unique_id: u64,
+) -> Vec<u8> {
+    // Variable headers first, their size sets the data offset
+    let mut producer = Vec::from(PRODUCER.as_bytes());
+    producer.push(0);
+    let mut var_headers = Vec::new();
+    var_headers.extend_from_slice(&((producer.len() + 4) as u16).to_le_bytes());
+    var_headers.extend_from_slice(b"sp");
+    var_headers.extend_from_slice(&producer);
+
+    let var_header_offset = FIXED_HEADER_LEN + blocks.len() * 8;
+    // Channel data starts on a 4 byte boundary, like xLights writes it
+    let data_offset = (var_header_offset + var_headers.len()).div_ceil(4) * 4;
+
+    let mut header = Vec::with_capacity(data_offset);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&(data_offset as u16).to_le_bytes());
+    header.push(MINOR_VERSION);
+    header.push(MAJOR_VERSION);
+    header.extend_from_slice(&(var_header_offset as u16).to_le_bytes());
+    header.extend_from_slice(&(channels as u32).to_le_bytes());
+    header.extend_from_slice(&(frames as u32).to_le_bytes());
+    header.push(step_ms);
+    header.push(0); // flags
+    header.push(compression.header_value());
+    header.push(blocks.len() as u8);
+    header.push(0); // sparse ranges
+    header.push(0); // flags
+    header.extend_from_slice(&unique_id.to_le_bytes());
+
+    for (frame, len) in blocks {
+        header.extend_from_slice(&(*frame as u32).to_le_bytes());
+        header.extend_from_slice(&(*len as u32).to_le_bytes());
+    }
+
+    header.extend_from_slice(&var_headers);
+    header.resize(data_offset, 0);
+    header
+}
+
+/// Problems reading an FSEQ file.
+#[derive(Debug)]
+pub enum FseqError {
+    Io(io::Error),
+    /// The file isn't a well-formed FSEQ file.
+    Invalid(&'static str),
+    /// A valid file using features this reader doesn't handle.
+    Unsupported(String),
+}
+
+impl fmt::Display for FseqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FseqError::Io(err) => write!(f, "{}", err),
+            FseqError::Invalid(msg) => write!(f, "invalid fseq file: {}", msg),
+            FseqError::Unsupported(msg) => write!(f, "unsupported fseq file: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FseqError {}
+
+impl From<io::Error> for FseqError {
+    fn from(err: io::Error) -> Self {
+        FseqError::Io(err)
+    }
+}
+
+/// Frames of channel data read back from a file.
+pub struct Sequence {
+    pub channels: usize,
+    pub step_ms: u8,
+    pub frames: Vec<Vec<u8>>,
+}
+
+fn u16_at(data: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([data[at], data[at + 1]]) as usize
+}
+
+fn u32_at(data: &[u8], at: usize) -> usize {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+}
+
+pub fn read<R: Read>(mut input: R) -> Result<Sequence, FseqError> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    if data.len() < FIXED_HEADER_LEN || &data[0..4] != MAGIC {
+        return Err(FseqError::Invalid("missing PSEQ header"));
+    }
+    if data[7] != MAJOR_VERSION {
+        return Err(FseqError::Unsupported(format!(
+            "version {}.{}",
+            data[7], data[6]
+        )));
+    }
+
+    let data_offset = u16_at(&data, 4);
+    let channels = u32_at(&data, 10);
+    let num_frames = u32_at(&data, 14);
+    let step_ms = data[18];
+    let compression = data[20] & 0x0f;
+    // v2.1+ keeps the upper bits of the block count in the compression byte
+    let num_blocks = ((data[20] as usize & 0xf0) << 4) | data[21] as usize;
+    if data[22] != 0 {
+        return Err(FseqError::Unsupported(String::from("sparse ranges")));
+    }
+    if data_offset > data.len() || FIXED_HEADER_LEN + num_blocks * 8 > data_offset {
+        return Err(FseqError::Invalid("header runs past channel data"));
+    }
+
+    let data_len = channels
+        .checked_mul(num_frames)
+        .ok_or(FseqError::Invalid("too much channel data"))?;
+
+    let channel_data = match compression {
+        0 => data[data_offset..].to_vec(),
+        1 => {
+            let mut channel_data = Vec::new();
+            let mut at = data_offset;
+            for i in 0..num_blocks {
+                let len = u32_at(&data, FIXED_HEADER_LEN + i * 8 + 4);
+                // Writers may reserve index entries they never fill
+                if len == 0 {
+                    continue;
+                }
+                if at + len > data.len() {
+                    return Err(FseqError::Invalid("compressed block runs past end of file"));
+                }
+                channel_data.extend(zstd::decode_all(&data[at..at + len])?);
+                at += len;
+            }
+            channel_data
+        }
+        2 => return Err(FseqError::Unsupported(String::from("zlib compression"))),
+        _ => return Err(FseqError::Invalid("unknown compression type")),
+    };
+
+    if channels == 0 || channel_data.len() < data_len {
+        return Err(FseqError::Invalid("less channel data than frames"));
+    }
+
+    let frames = channel_data
+        .chunks_exact(channels)
+        .take(num_frames)
+        .map(|frame| frame.to_vec())
+        .collect();
+
+    Ok(Sequence {
+        channels,
+        step_ms,
+        frames,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(compression: Compression, num_frames: usize) -> Vec<u8> {
+        let channels = 9;
+        let mut writer =
+            FseqWriter::new(Cursor::new(Vec::new()), channels, 33, compression, 1000).unwrap();
+        let frames: Vec<Vec<u8>> = (0..num_frames)
+            .map(|i| (0..channels).map(|c| (i * 7 + c) as u8).collect())
+            .collect();
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+
+        let sequence = read(file.as_slice()).unwrap();
+        assert_eq!(sequence.channels, channels);
+        assert_eq!(sequence.step_ms, 33);
+        assert_eq!(sequence.frames, frames);
+        file
+    }
+
+    #[test]
+    fn uncompressed_round_trip() {
+        let file = round_trip(Compression::None, 20);
+        assert_eq!(&file[0..4], b"PSEQ");
+        assert_eq!(file[7], 2);
+        assert_eq!(u32_at(&file, 10), 9);
+        assert_eq!(u32_at(&file, 14), 20);
+        assert_eq!(file[20], 0);
+        assert_eq!(file.len(), u16_at(&file, 4) + 9 * 20);
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        // Small frames all fit in one block
+        let file = round_trip(Compression::Zstd, 21);
+        assert_eq!(file[20], 1);
+        assert_eq!(file[21], 1);
+    }
+
+    /// First frame of each block in a zstd file of `frames` frames.
+    fn block_starts(channels: usize, frames: usize, max_frames: usize) -> Vec<usize> {
+        let mut writer = FseqWriter::new(
+            Cursor::new(Vec::new()),
+            channels,
+            33,
+            Compression::Zstd,
+            max_frames,
+        )
+        .unwrap();
+        for _ in 0..frames {
+            writer.write_frame(&vec![0; channels]).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+        assert_eq!(read(file.as_slice()).unwrap().frames.len(), frames);
+        (0..file[21] as usize)
+            .map(|i| u32_at(&file, 32 + i * 8))
+            .collect()
+    }
+
+    #[test]
+    fn block_sizes() {
+        // 16 KiB frames make 4 frame blocks, ending on a partial one
+        assert_eq!(block_starts(16384, 21, 1000), vec![0, 4, 8, 12, 16, 20]);
+        // Unless that would take more than 255 blocks
+        assert_eq!(block_starts(16384, 21, 2550), vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn deterministic_id() {
+        assert_eq!(
+            round_trip(Compression::Zstd, 5),
+            round_trip(Compression::Zstd, 5)
+        );
+    }
+
+    #[test]
+    fn too_many_blocks() {
+        let frame = vec![0; MIN_BLOCK_BYTES];
+        let mut writer = FseqWriter::new(
+            Cursor::new(Vec::new()),
+            frame.len(),
+            33,
+            Compression::Zstd,
+            10,
+        )
+        .unwrap();
+        // One frame a block, so the 256th frame has nowhere to go
+        for _ in 0..MAX_BLOCKS {
+            writer.write_frame(&frame).unwrap();
+        }
+        let err = writer.write_frame(&frame).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn huge_header() {
+        let mut file = round_trip(Compression::Zstd, 5);
+        file[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        file[14..18].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(read(file.as_slice()), Err(FseqError::Invalid(_))));
+    }
+
+    #[test]
+    fn step_times() {
+        assert_eq!(step_time_ms(30), Some(33));
+        assert_eq!(step_time_ms(40), Some(25));
+        assert_eq!(step_time_ms(0), None);
+        assert_eq!(step_time_ms(2000), None);
+    }
+}
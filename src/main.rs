@@ -1,13 +1,24 @@
-use macroquad::prelude::*;
-use std::collections::HashMap;
-use std::time::Instant;
+use std::io::{Error, ErrorKind};
+#[cfg(feature = "viewer")]
+use std::rc::Rc;
 use structopt::StructOpt;
 
+mod colors;
+mod correction;
 mod export;
+mod fseq;
+mod output;
 mod patterns;
+// Only the viewer plays files back for now
+#[cfg_attr(not(feature = "viewer"), allow(dead_code))]
+mod playback;
+mod power;
+mod render;
 mod tree;
+#[cfg(feature = "viewer")]
+mod viewer;
 
-use patterns::Pattern;
+use patterns::{PatternArgs, PatternInfo};
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -21,20 +32,33 @@ struct Opt {
 
 #[derive(Debug, StructOpt)]
 enum Command {
+    #[cfg(feature = "viewer")]
     View {
         #[structopt(flatten)]
         common: CommonFlags,
 
-        /// RPM to spin model at in view.
+        /// RPM to auto-rotate model at in view.
         #[structopt(short, long, default_value = "5")]
         rpm: u32,
 
         /// FPS to display pattern at inside of view.
         #[structopt(short, long, default_value = "30")]
         fps: u32,
+
+        #[structopt(flatten)]
+        power: PowerFlags,
+
+        /// Also send whatever's on screen to a live output, see `run`.
+        #[structopt(short, long = "output", number_of_values = 1)]
+        outputs: Vec<output::OutputSpec>,
+
+        /// Show frames sent by OPC clients instead of a pattern, listening on
+        /// `host[:port]` (port 7890 by default).
+        #[structopt(long = "opc-server")]
+        opc_server: Option<String>,
     },
     Export {
-        /// File to write output to
+        /// File to write output to, `-` for stdout.
         output: String,
 
         #[structopt(flatten)]
@@ -43,6 +67,113 @@ enum Command {
         /// Maximum number of frames of pattern to export.
         #[structopt(long = "max-frames", default_value = "1000")]
         max_frames: usize,
+
+        /// Output format, `csv` or `fseq`.
+        #[structopt(long, default_value = "csv")]
+        format: export::Format,
+
+        /// Write raw 0.0-1.0 float colour values instead of 0-255 integers (CSV only).
+        #[structopt(long)]
+        float: bool,
+
+        /// FSEQ compression, `zstd` or `none`.
+        #[structopt(long, default_value = "zstd")]
+        compression: fseq::Compression,
+
+        /// Colour correction for the LEDs the export is for, as
+        /// `key=value;...` with keys order, gamma, lut, brightness and balance.
+        #[structopt(long, default_value = "")]
+        correction: correction::Correction,
+
+        #[structopt(flatten)]
+        power: PowerFlags,
+
+        /// Frame rate the export will be played back at.
+        #[structopt(short, long, default_value = "30")]
+        fps: u32,
+    },
+    /// Play back an exported CSV or FSEQ animation in the viewer.
+    #[cfg(feature = "viewer")]
+    Play {
+        /// CSV or `.fseq` file of frames, as written by `export`.
+        file: String,
+
+        /// Tree to use as model.
+        #[structopt(short, long, default_value = "data/mattparker_2021.csv")]
+        tree: String,
+
+        /// RPM to auto-rotate model at in view.
+        #[structopt(short, long, default_value = "5")]
+        rpm: u32,
+
+        /// FPS to play frames at. Defaults to the file's own rate for FSEQ, 30 for CSV.
+        #[structopt(short, long)]
+        fps: Option<u32>,
+    },
+    /// Render a pattern on the tree to an animated GIF or PNG frames, no window needed.
+    Render {
+        /// `.gif` for an animated GIF, or `.png` for numbered frames
+        /// (`out.png` writes `out_0000.png`, `out_0001.png`, ...).
+        output: String,
+
+        #[structopt(flatten)]
+        common: CommonFlags,
+
+        #[structopt(flatten)]
+        camera: CameraFlags,
+
+        /// Maximum number of frames of pattern to render.
+        #[structopt(long = "max-frames", default_value = "150")]
+        max_frames: usize,
+
+        /// FPS to render pattern at.
+        #[structopt(short, long, default_value = "30")]
+        fps: u32,
+
+        /// Image width in pixels.
+        #[structopt(long, default_value = "360")]
+        width: usize,
+
+        /// Image height in pixels.
+        #[structopt(long, default_value = "480")]
+        height: usize,
+
+        /// LED radius, in the tree's units.
+        #[structopt(long = "led-radius", default_value = "0.01")]
+        led_radius: f32,
+    },
+    /// Run a pattern headless, straight out to live outputs.
+    Run {
+        #[structopt(flatten)]
+        common: CommonFlags,
+
+        /// Where to send frames, as `kind[:address][;key=value...]`. Can be
+        /// given more than once. Kinds are `sacn` (options universe, channel,
+        /// priority; multicast without an address), `artnet` (options net,
+        /// subnet, universe, sync), `ddp`, WLED's `drgb` and `dnrgb`
+        /// (option timeout), `opc` (option channel) and `adalight:<path>`
+        /// (options baud, framing). Every kind also takes colour correction
+        /// options order, gamma, lut, brightness and balance.
+        #[structopt(short, long = "output", required = true, number_of_values = 1)]
+        outputs: Vec<output::OutputSpec>,
+
+        #[structopt(flatten)]
+        power: PowerFlags,
+
+        /// FPS to run pattern at.
+        #[structopt(short, long, default_value = "30")]
+        fps: u32,
+
+        /// Stop after this many frames, otherwise runs until the pattern ends.
+        #[structopt(long = "max-frames")]
+        max_frames: Option<usize>,
+    },
+    /// List every available pattern.
+    ListPatterns,
+    /// Show a pattern's description and the arguments it accepts.
+    DescribePattern {
+        /// Name of the pattern.
+        name: String,
     },
 }
 
@@ -52,135 +183,413 @@ struct CommonFlags {
     #[structopt(short, long, default_value = "data/mattparker_2021.csv")]
     tree: String,
 
-    /// Extra arguments to pass into pattern. Semicolon-separated key=value pairs.
+    /// Pattern to run. See `list-patterns` for the available patterns.
+    #[structopt(short, long, default_value = "balls")]
+    pattern: String,
+
+    /// Extra arguments to pass into pattern. Semicolon-separated key=value pairs,
+    /// see `describe-pattern` for what each pattern accepts.
     #[structopt(long = "pattern-args")]
     pattern_args: Option<String>,
+
+    /// Seed for patterns' randomness. Picked at random if not given, the seed
+    /// used is saved alongside exports so they can be reproduced.
+    #[structopt(long)]
+    seed: Option<u64>,
+}
+
+// How much current the tree draws, and how much the supply can give. Not a doc
+// comment, structopt would show it in place of each command's own.
+#[derive(Debug, StructOpt)]
+struct PowerFlags {
+    /// Current one LED channel draws at full brightness, in mA.
+    #[structopt(
+        long = "ma-per-channel",
+        default_value = "20",
+        parse(try_from_str = positive_milliamps)
+    )]
+    ma_per_channel: f32,
+
+    /// Current each LED draws even when off, in mA.
+    #[structopt(long = "idle-ma", default_value = "1", parse(try_from_str = milliamps))]
+    idle_ma: f32,
+
+    /// Supply limit in mA. Frames that would draw more are dimmed to fit.
+    #[structopt(long = "power-limit", parse(try_from_str = positive_milliamps))]
+    power_limit: Option<f32>,
 }
 
-// TODO assure pixels/frame line up
-fn render_frame(tree: &Vec<tree::Pixel>, frame: &Vec<Color>) {
-    let mut i = 0;
-    for pixel in tree {
-        let location = vec3(pixel.x, pixel.y, pixel.z);
-        let color = frame[i];
-        draw_sphere(location, 0.01, None, color);
-        i += 1;
+/// Parses a current in mA, which can't be negative.
+fn milliamps(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(ma) if ma.is_finite() && ma >= 0. => Ok(ma),
+        _ => Err(format!("\"{}\" isn't a current of 0 mA or more", s)),
     }
 }
 
-fn parse_extra_args(args: Option<String>) -> HashMap<String, String> {
-    if let Some(args) = args {
-        let mut map = HashMap::new();
-        for arg in args.split(';') {
-            let mut kv = arg.split('=');
-            let key = String::from(kv.next().unwrap());
-            let val = String::from(kv.next().unwrap());
-            map.insert(key, val);
-        }
+/// Parses a current in mA that has to be more than none.
+fn positive_milliamps(s: &str) -> Result<f32, String> {
+    match milliamps(s) {
+        Ok(ma) if ma > 0. => Ok(ma),
+        _ => Err(format!("\"{}\" isn't a current above 0 mA", s)),
+    }
+}
 
-        map
-    } else {
-        HashMap::new()
+impl PowerFlags {
+    fn model(&self) -> power::PowerModel {
+        power::PowerModel {
+            milliamps_per_channel: self.ma_per_channel,
+            idle_milliamps: self.idle_ma,
+            limit: self.power_limit,
+        }
     }
 }
 
-async fn render_loop<T: Pattern>(
-    tree: Vec<tree::Pixel>,
-    mut pattern: T,
-    rpm: u32,
-    fps: u32,
-    args: HashMap<String, String>,
-) {
-    // Pre-calculate rotational velocity of scene
-    let rot_vel: f32 = std::f32::consts::PI * 2. * (rpm as f32 / 60.);
-    // Too lazy to do fixed-point math
-    let frame_time_ms: u32 = (1. / fps as f32 * 1000.) as u32;
-
-    // Prep rotation
-    let mut prev_frame_time = Instant::now();
-    let mut theta: f32 = 0.;
-
-    let mut current_frame = pattern.next_frame().unwrap();
-
-    loop {
-        // Set up basic scene
-        clear_background(DARKGRAY);
-
-        let frame_time = Instant::now();
-        let delta = frame_time - prev_frame_time;
-
-        // Approximate an Nfps cap, probably better ways to do this that I can impl later
-        if delta.as_millis() as u32 > frame_time_ms {
-            prev_frame_time = frame_time; // update previous frame time
-
-            // Update pattern
-            // TODO improve this iteration strategy
-            current_frame = match pattern.next_frame() {
-                Some(frame) => frame,
-                None => {
-                    pattern = T::from_tree(&tree, &args);
-                    pattern.next_frame().unwrap()
-                }
-            }
+// Where `render` looks at the tree from. Defaults match the viewer's. Not a doc
+// comment, structopt would show it as `render`'s description.
+#[derive(Debug, StructOpt)]
+struct CameraFlags {
+    /// RPM to rotate the camera around the tree at.
+    #[structopt(short, long, default_value = "5")]
+    rpm: f32,
+
+    /// Starting angle around the tree in degrees, 0 looks along -Y.
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    yaw: f32,
+
+    /// Angle above the horizon in degrees, stopping just short of straight
+    /// up or down.
+    #[structopt(long, allow_hyphen_values = true)]
+    pitch: Option<f32>,
+
+    /// Distance from the centre of the tree, in the tree's units. Defaults to
+    /// far enough to fit the whole tree in.
+    #[structopt(long)]
+    distance: Option<f32>,
+
+    /// Vertical field of view in degrees.
+    #[structopt(long, default_value = "45")]
+    fov: f32,
+}
+
+impl CameraFlags {
+    fn camera(&self, tree: &[tree::Pixel]) -> render::Camera {
+        let framing = render::Camera::framing(tree);
+        render::Camera {
+            yaw: self.yaw.to_radians(),
+            pitch: self.pitch.map_or(framing.pitch, f32::to_radians),
+            distance: self.distance.unwrap_or(framing.distance),
+            fov: self.fov.to_radians(),
+            ..framing
         }
+    }
+}
+
+fn load_tree(path: &str) -> std::io::Result<Vec<tree::Pixel>> {
+    match tree::import_tree(path) {
+        Ok(tree) => Ok(tree),
+        Err(err) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {}", path, err),
+        )),
+    }
+}
+
+fn find_pattern(name: &str) -> std::io::Result<&'static PatternInfo> {
+    match patterns::find(name) {
+        Some(info) => Ok(info),
+        None => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unknown pattern \"{}\", see list-patterns", name),
+        )),
+    }
+}
+
+fn open_outputs(
+    specs: &[output::OutputSpec],
+    power: power::PowerModel,
+) -> std::io::Result<Vec<output::OpenOutput>> {
+    specs.iter().map(|spec| spec.open(power)).collect()
+}
+
+/// The viewer steps patterns by `1 / fps` seconds, which 0 can't do.
+#[cfg(feature = "viewer")]
+fn check_fps(fps: u32) -> std::io::Result<()> {
+    if fps == 0 {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            "fps must be at least 1",
+        ))
+    } else {
+        Ok(())
+    }
+}
 
-        // Set up camera
-        theta += (delta.as_millis() as f32) / 1000. * rot_vel; // Update camera angle
-        set_camera(&Camera3D {
-            position: vec3(theta.sin() * 4., theta.cos() * 4., 3.),
-            target: vec3(0., 0., 1.5),
-            up: vec3(0., 0., 1.),
-            ..Default::default()
-        });
+fn describe_pattern(info: &PatternInfo) {
+    println!("{}: {}", info.name, info.description);
+    if info.params.is_empty() {
+        println!("\nTakes no arguments.");
+        return;
+    }
 
-        // Draw pixels
-        render_frame(&tree, &current_frame);
+    println!(
+        "\n{:<14} {:<6} {:<8} {:<16} DESCRIPTION",
+        "ARGUMENT", "TYPE", "DEFAULT", "RANGE"
+    );
+    for param in info.params {
+        println!(
+            "{:<14} {:<6} {:<8} {:<16} {}",
+            param.name,
+            param.default.type_name(),
+            param.default.to_string(),
+            format!("{} to {}", param.min, param.max),
+            param.description
+        );
+        if !param.choices.is_empty() {
+            println!("{:<48}Names: {}", "", param.choices.join(", "));
+        }
+    }
+}
 
-        set_default_camera();
-        next_frame().await;
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
     }
 }
 
-#[macroquad::main("Merry Chrysler")]
-async fn main() -> std::io::Result<()> {
+// Only `view` opens a window, everything else runs headless.
+fn run() -> std::io::Result<()> {
     let opts = Opt::from_args();
 
     let flags = match opts.command {
+        #[cfg(feature = "viewer")]
         Command::View {
             ref common,
             rpm: _,
             fps: _,
+            power: _,
+            outputs: _,
+            opc_server: _,
         } => common,
         Command::Export {
             output: _,
             ref common,
             max_frames: _,
+            format: _,
+            float: _,
+            compression: _,
+            correction: _,
+            power: _,
+            fps: _,
+        } => common,
+        Command::Render {
+            output: _,
+            ref common,
+            camera: _,
+            max_frames: _,
+            fps: _,
+            width: _,
+            height: _,
+            led_radius: _,
+        } => common,
+        Command::Run {
+            ref common,
+            outputs: _,
+            power: _,
+            fps: _,
+            max_frames: _,
         } => common,
+        Command::ListPatterns => {
+            let width = patterns::PATTERNS
+                .iter()
+                .map(|info| info.name.len())
+                .max()
+                .unwrap_or(0);
+            for info in patterns::PATTERNS {
+                println!("{:<width$} {}", info.name, info.description, width = width);
+            }
+            return Ok(());
+        }
+        Command::DescribePattern { ref name } => {
+            describe_pattern(find_pattern(name.as_str())?);
+            return Ok(());
+        }
+        #[cfg(feature = "viewer")]
+        Command::Play {
+            ref file,
+            ref tree,
+            rpm,
+            fps,
+        } => {
+            let tree = load_tree(tree.as_str())?;
+            let imported = if file.ends_with(".fseq") {
+                playback::import_fseq(file.as_str(), tree.len())
+                    .map(|(frames, step_ms)| (frames, 1000 / step_ms.max(1) as u32))
+            } else {
+                playback::import_csv(file.as_str(), tree.len()).map(|frames| (frames, 30))
+            };
+            let (frames, file_fps) = match imported {
+                Ok((frames, fps)) => (Rc::new(frames), fps),
+                Err(err) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{}: {}", file, err),
+                    ))
+                }
+            };
+            let fps = fps.unwrap_or(file_fps);
+            check_fps(fps)?;
+
+            let factory: viewer::PatternFactory =
+                Box::new(move |_| Box::new(playback::FramePlayback::new(frames.clone())));
+            macroquad::Window::new(
+                "Merry Chrysler",
+                viewer::render_loop(
+                    tree,
+                    file.clone(),
+                    factory,
+                    rpm,
+                    fps,
+                    power::PowerModel::default(),
+                    Vec::new(),
+                ),
+            );
+            return Ok(());
+        }
     };
 
-    let tree = tree::import_tree(flags.tree.as_str())?;
-    let extra_args = parse_extra_args(flags.pattern_args.clone());
+    // Validate pattern and its arguments before doing any real work
+    let pattern_info = find_pattern(flags.pattern.as_str())?;
+    let pattern_args = match PatternArgs::parse(pattern_info.params, flags.pattern_args.as_deref())
+    {
+        Ok(args) => args,
+        Err(err) => return Err(Error::new(ErrorKind::InvalidInput, err)),
+    };
 
-    // Prep pattern
-    // TODO make this dynamic CLI (unfortunately requires dyn stuff I'm not comfortable with yet)
-    let mut pattern = patterns::balls::BallPattern::from_tree(&tree, &extra_args);
+    let seed = flags.seed.unwrap_or_else(rand::random);
+    let tree = load_tree(flags.tree.as_str())?;
 
     match opts.command {
         Command::Export {
             output,
             common: _,
             max_frames,
+            format,
+            float,
+            compression,
+            correction,
+            power,
+            fps,
         } => {
-            export::export_pattern(&tree, &mut pattern, max_frames, output.as_str())?;
-            return Ok(());
+            let mut pattern = pattern_info.create(&tree, &pattern_args, seed);
+            let options = export::ExportOptions {
+                format,
+                frame_limit: max_frames,
+                fps,
+                float,
+                compression,
+                correction,
+                power: power.model(),
+            };
+            export::export_pattern(&tree, pattern.as_mut(), output.as_str(), &options)?;
+
+            let info = export::ExportInfo {
+                pattern: pattern_info.name,
+                args: &pattern_args,
+                seed,
+                fps,
+            };
+            if output == "-" {
+                eprint!("{}", info.metadata());
+                Ok(())
+            } else {
+                info.write_metadata(output.as_str())
+            }
+        }
+        Command::Render {
+            output,
+            common: _,
+            camera,
+            max_frames,
+            fps,
+            width,
+            height,
+            led_radius,
+        } => {
+            let mut pattern = pattern_info.create(&tree, &pattern_args, seed);
+            let options = render::RenderOptions {
+                width,
+                height,
+                frame_limit: max_frames,
+                fps,
+                rpm: camera.rpm,
+                led_radius,
+            };
+            render::render_pattern(
+                &tree,
+                pattern.as_mut(),
+                output.as_str(),
+                &camera.camera(&tree),
+                &options,
+            )?;
+
+            export::ExportInfo {
+                pattern: pattern_info.name,
+                args: &pattern_args,
+                seed,
+                fps,
+            }
+            .write_metadata(output.as_str())
+        }
+        Command::Run {
+            common: _,
+            outputs,
+            power,
+            fps,
+            max_frames,
+        } => {
+            let mut outputs = open_outputs(&outputs, power.model())?;
+            let mut pattern = pattern_info.create(&tree, &pattern_args, seed);
+            eprintln!("running {} (seed {})", pattern_info.name, seed);
+            output::stream(pattern.as_mut(), &mut outputs, fps, max_frames)?;
+            // Each output is corrected and limited on its own
+            for (i, output) in outputs.iter().enumerate() {
+                if outputs.len() > 1 {
+                    eprint!("output {} ", i + 1);
+                }
+                eprintln!("{}", output.get_ref().stats);
+            }
+            Ok(())
         }
+        #[cfg(feature = "viewer")]
         Command::View {
             common: _,
             rpm,
             fps,
+            power,
+            outputs,
+            opc_server,
         } => {
-            render_loop(tree, pattern, rpm, fps, extra_args).await;
-            //patterns::balls::run_ball_loop(pattern, rpm, fps).await;
+            check_fps(fps)?;
+            let outputs = open_outputs(&outputs, power.model())?;
+            let (factory, name): (viewer::PatternFactory, String) = match opc_server {
+                Some(address) => {
+                    let address = output::parse_address(address.as_str(), output::opc::PORT)
+                        .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+                    let server = output::opc::OpcServer::bind(address, tree.len())?;
+                    let name = format!("OPC server on {}", server.local_addr());
+                    (Box::new(move |_| Box::new(server.frames())), name)
+                }
+                None => (
+                    Box::new(move |tree| pattern_info.create(tree, &pattern_args, seed)),
+                    format!("{} (seed {})", pattern_info.name, seed),
+                ),
+            };
+            macroquad::Window::new(
+                "Merry Chrysler",
+                viewer::render_loop(tree, name, factory, rpm, fps, power.model(), outputs),
+            );
             Ok(())
         }
         _ => Ok(()),
@@ -0,0 +1,276 @@
+//! Adalight output, for microcontrollers driving LEDs over USB serial.
+//!
+//! Each frame is an `Ada` header, the LED count less one (16 bits, big
+//! endian), a checksum of the count bytes XOR 0x55, then the pixel data.
+//!
+//! Any path can be written to. Serial ports and pseudo-terminals get their
+//! baud rate and framing set first, anything else (a plain file, a FIFO) just
+//! gets the bytes. Paths under `/dev` have to exist already, so a mistyped or
+//! unplugged port is an error rather than a new file.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::Output;
+use crate::colors::{channel_to_u8, Color};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Serial character framing, as in `8N1`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Framing {
+    /// 5-8
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2
+    pub stop_bits: u8,
+}
+
+impl Default for Framing {
+    /// 8N1, which is what everything uses anyway.
+    fn default() -> Self {
+        Framing {
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+        }
+    }
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("framing \"{}\" isn't like 8N1", s);
+        let chars: Vec<char> = s.chars().collect();
+        if chars.len() != 3 {
+            return Err(bad());
+        }
+
+        let data_bits = match chars[0] {
+            '5'..='8' => chars[0] as u8 - b'0',
+            _ => return Err(bad()),
+        };
+        let parity = match chars[1].to_ascii_uppercase() {
+            'N' => Parity::None,
+            'E' => Parity::Even,
+            'O' => Parity::Odd,
+            _ => return Err(bad()),
+        };
+        let stop_bits = match chars[2] {
+            '1' => 1,
+            '2' => 2,
+            _ => return Err(bad()),
+        };
+
+        Ok(Framing {
+            data_bits,
+            parity,
+            stop_bits,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdalightConfig {
+    /// Serial port, pty or any other file to write to.
+    pub path: String,
+    pub baud: u32,
+    pub framing: Framing,
+}
+
+/// Writes frames out Adalight framed.
+pub struct AdalightSender {
+    file: File,
+}
+
+impl AdalightSender {
+    pub fn open(config: &AdalightConfig) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.write(true);
+        if !Path::new(&config.path).starts_with("/dev") {
+            options.create(true).truncate(true);
+        }
+        // Don't let a serial port become our controlling terminal
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NOCTTY);
+        }
+
+        let file = options.open(&config.path)?;
+        if file.is_terminal() {
+            configure_port(&file, config.baud, config.framing)?;
+        }
+
+        Ok(AdalightSender { file })
+    }
+}
+
+impl Output for AdalightSender {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        // Nothing to send, and no way to say zero LEDs anyway
+        if frame.is_empty() {
+            return Ok(());
+        }
+        if frame.len() > u16::MAX as usize + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Adalight can only address 65536 LEDs",
+            ));
+        }
+
+        let mut message = header(frame.len()).to_vec();
+        for color in frame {
+            message.push(channel_to_u8(color.r));
+            message.push(channel_to_u8(color.g));
+            message.push(channel_to_u8(color.b));
+        }
+        // One write per frame, so the microcontroller sees it arrive together
+        self.file.write_all(&message)?;
+        self.file.flush()
+    }
+}
+
+/// Header for a frame of `num_leds`, at least 1.
+pub fn header(num_leds: usize) -> [u8; 6] {
+    let [hi, lo] = ((num_leds - 1) as u16).to_be_bytes();
+    [b'A', b'd', b'a', hi, lo, hi ^ lo ^ 0x55]
+}
+
+#[cfg(unix)]
+fn configure_port(file: &File, baud: u32, framing: Framing) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    use termios::*;
+
+    let speed = match baud {
+        9600 => B9600,
+        19200 => B19200,
+        38400 => B38400,
+        57600 => os::target::B57600,
+        115200 => os::target::B115200,
+        230400 => os::target::B230400,
+        #[cfg(target_os = "linux")]
+        460800 => os::target::B460800,
+        #[cfg(target_os = "linux")]
+        500000 => os::target::B500000,
+        #[cfg(target_os = "linux")]
+        921600 => os::target::B921600,
+        #[cfg(target_os = "linux")]
+        1000000 => os::target::B1000000,
+        #[cfg(target_os = "linux")]
+        2000000 => os::target::B2000000,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported baud rate {}", baud),
+            ))
+        }
+    };
+
+    let fd = file.as_raw_fd();
+    let mut termios = Termios::from_fd(fd)?;
+    // No line editing, echo or newline translation, just bytes
+    cfmakeraw(&mut termios);
+    cfsetspeed(&mut termios, speed)?;
+
+    termios.c_cflag &= !(CSIZE | PARENB | PARODD | CSTOPB);
+    termios.c_cflag |= CLOCAL
+        | CREAD
+        | match framing.data_bits {
+            5 => CS5,
+            6 => CS6,
+            7 => CS7,
+            _ => CS8,
+        };
+    match framing.parity {
+        Parity::None => {}
+        Parity::Even => termios.c_cflag |= PARENB,
+        Parity::Odd => termios.c_cflag |= PARENB | PARODD,
+    }
+    if framing.stop_bits == 2 {
+        termios.c_cflag |= CSTOPB;
+    }
+
+    tcsetattr(fd, TCSANOW, &termios)
+}
+
+// Serial setup is only done on unix, elsewhere the port is used as it is
+#[cfg(not(unix))]
+fn configure_port(_file: &File, _baud: u32, _framing: Framing) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers() {
+        assert_eq!(header(1), [b'A', b'd', b'a', 0, 0, 0x55]);
+        // 500 LEDs is a count of 499, 0x01f3
+        assert_eq!(
+            header(500),
+            [b'A', b'd', b'a', 0x01, 0xf3, 0x01 ^ 0xf3 ^ 0x55]
+        );
+    }
+
+    #[test]
+    fn framing() {
+        assert_eq!(
+            "7e2".parse(),
+            Ok(Framing {
+                data_bits: 7,
+                parity: Parity::Even,
+                stop_bits: 2
+            })
+        );
+        assert!("9N1".parse::<Framing>().is_err());
+        assert!("8N".parse::<Framing>().is_err());
+    }
+
+    #[test]
+    fn write_to_file() {
+        let path = std::env::temp_dir().join(format!("xmas-mapper-ada-{}", std::process::id()));
+        let config = AdalightConfig {
+            path: String::from(path.to_str().unwrap()),
+            baud: 115200,
+            framing: "8N1".parse().unwrap(),
+        };
+
+        let mut sender = AdalightSender::open(&config).unwrap();
+        let frame = [Color::new(1., 0.2, 0., 1.), Color::new(0., 0., 1., 1.)];
+        sender.send_frame(&frame).unwrap();
+        sender.send_frame(&frame[..1]).unwrap();
+        drop(sender);
+
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            vec![
+                b'A', b'd', b'a', 0, 1, 0x54, 255, 51, 0, 0, 0, 255, // First frame
+                b'A', b'd', b'a', 0, 0, 0x55, 255, 51, 0, // Second frame
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_device() {
+        let config = AdalightConfig {
+            path: String::from("/dev/xmas-mapper-missing"),
+            baud: 115200,
+            framing: Framing::default(),
+        };
+        let err = AdalightSender::open(&config).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!Path::new(&config.path).exists());
+    }
+}
@@ -0,0 +1,208 @@
+//! Art-Net output.
+//!
+//! Pixels go out as ArtDMX packets over consecutive port addresses, 170 to a
+//! universe. With sync on, an ArtSync follows each frame so the node latches
+//! every universe at once instead of tearing between them.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+use super::{dmx, Output};
+use crate::colors::Color;
+
+pub const PORT: u16 = 6454;
+const ID: &[u8; 8] = b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+const OP_SYNC: u16 = 0x5200;
+const PROTOCOL_VERSION: u16 = 14;
+/// Port addresses are 15 bits, net:subnet:universe as 7:4:4.
+const MAX_PORT_ADDRESS: u16 = 0x7fff;
+
+#[derive(Clone, Debug)]
+pub struct ArtNetConfig {
+    /// Node to send to, or a broadcast address.
+    pub destination: SocketAddr,
+    /// 0-127
+    pub net: u8,
+    /// 0-15
+    pub subnet: u8,
+    /// Universe within the subnet the first pixel goes in, 0-15.
+    pub universe: u8,
+    /// Send an ArtSync after every frame.
+    pub sync: bool,
+}
+
+impl ArtNetConfig {
+    /// 15-bit port address of the first universe.
+    pub fn port_address(&self) -> u16 {
+        (self.net as u16) << 8 | (self.subnet as u16) << 4 | self.universe as u16
+    }
+}
+
+/// Sends frames as ArtDMX packets.
+pub struct ArtNetSender {
+    socket: UdpSocket,
+    config: ArtNetConfig,
+    /// Shared by every universe in a frame, 0 would turn reordering checks off.
+    sequence: u8,
+}
+
+impl ArtNetSender {
+    pub fn new(config: ArtNetConfig) -> io::Result<Self> {
+        if config.net > 127 || config.subnet > 15 || config.universe > 15 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Art-Net net must be 0-127, subnet and universe 0-15",
+            ));
+        }
+
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        // Nodes are often addressed by subnet broadcast
+        socket.set_broadcast(true)?;
+        Ok(ArtNetSender {
+            socket,
+            config,
+            sequence: 1,
+        })
+    }
+}
+
+impl Output for ArtNetSender {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        let universes = dmx::universes(frame, 0);
+        let first = self.config.port_address();
+        let last = first as usize + universes.len().saturating_sub(1);
+        if last > MAX_PORT_ADDRESS as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} pixels run past the last Art-Net port address",
+                    frame.len()
+                ),
+            ));
+        }
+
+        for (i, channels) in universes.iter().enumerate() {
+            let packet = dmx_packet(self.sequence, first + i as u16, channels);
+            self.socket.send_to(&packet, self.config.destination)?;
+        }
+        if self.config.sync {
+            self.socket
+                .send_to(&sync_packet(), self.config.destination)?;
+        }
+
+        self.sequence = self.sequence.checked_add(1).unwrap_or(1);
+        Ok(())
+    }
+}
+
+fn header(opcode: u16) -> Vec<u8> {
+    let mut packet = Vec::from(&ID[..]);
+    // Opcode's little endian, unlike everything else
+    packet.extend_from_slice(&opcode.to_le_bytes());
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet
+}
+
+/// Builds an ArtDMX packet carrying `channels` for `port_address`.
+pub fn dmx_packet(sequence: u8, port_address: u16, channels: &[u8]) -> Vec<u8> {
+    let mut packet = header(OP_DMX);
+    packet.push(sequence);
+    packet.push(0); // Physical port, informational only
+    packet.extend_from_slice(&port_address.to_le_bytes()); // SubUni, then Net
+
+    // Length has to be even
+    let len = channels.len() + channels.len() % 2;
+    packet.extend_from_slice(&(len as u16).to_be_bytes());
+    packet.extend_from_slice(channels);
+    packet.resize(packet.len() + len - channels.len(), 0);
+    packet
+}
+
+pub fn sync_packet() -> Vec<u8> {
+    let mut packet = header(OP_SYNC);
+    packet.extend_from_slice(&[0, 0]); // Aux1, Aux2
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::channel_to_u8;
+    use crate::output::stream;
+    use crate::patterns::{self, FrameContext, PatternArgs};
+    use glam::vec3;
+    use std::time::Duration;
+
+    #[test]
+    fn port_addresses() {
+        let config = ArtNetConfig {
+            destination: SocketAddr::from(([127, 0, 0, 1], PORT)),
+            net: 3,
+            subnet: 2,
+            universe: 15,
+            sync: false,
+        };
+        assert_eq!(config.port_address(), 0x032f);
+        assert!(ArtNetSender::new(ArtNetConfig { net: 128, ..config }).is_err());
+    }
+
+    #[test]
+    fn local_node() {
+        let node = UdpSocket::bind("127.0.0.1:0").unwrap();
+        node.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // 171 pixels, so the second universe gets one pixel and an odd length
+        let tree: Vec<_> = (0..171).map(|i| vec3(i as f32 / 100., 0., 0.)).collect();
+        let config = ArtNetConfig {
+            destination: node.local_addr().unwrap(),
+            net: 1,
+            subnet: 0,
+            universe: 15,
+            sync: true,
+        };
+        let mut sender = ArtNetSender::new(config).unwrap();
+
+        let info = patterns::find("rainbow").unwrap();
+        let args = PatternArgs::parse(info.params, None).unwrap();
+        let mut pattern = info.create(&tree, &args, 1);
+        stream(pattern.as_mut(), &mut sender, 1000, Some(2)).unwrap();
+
+        let mut expected = info.create(&tree, &args, 1);
+        let mut buf = [0; 1024];
+        for frame_index in 0..2 {
+            let frame = expected
+                .next_frame(&FrameContext::at_rate(frame_index, 1000))
+                .unwrap();
+
+            // Universe 15 of net 1 subnet 0 rolls over into subnet 1 universe 0
+            for (sub_uni, pixels) in [(0x0f, &frame[..170]), (0x10, &frame[170..])] {
+                let len = node.recv(&mut buf).unwrap();
+                let packet = &buf[..len];
+
+                assert_eq!(&packet[..8], b"Art-Net\0");
+                assert_eq!(&packet[8..10], &[0x00, 0x50]);
+                assert_eq!(&packet[10..12], &[0, 14]);
+                assert_eq!(packet[12], frame_index as u8 + 1);
+                assert_eq!(&packet[14..16], &[sub_uni, 1]);
+
+                let channels: Vec<u8> = pixels
+                    .iter()
+                    .flat_map(|c| vec![c.r, c.g, c.b])
+                    .map(channel_to_u8)
+                    .collect();
+                let data_len = channels.len() + channels.len() % 2;
+                assert_eq!(
+                    u16::from_be_bytes([packet[16], packet[17]]) as usize,
+                    data_len
+                );
+                assert_eq!(len, 18 + data_len);
+                assert_eq!(&packet[18..18 + channels.len()], channels.as_slice());
+            }
+
+            let len = node.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], &sync_packet()[..]);
+            assert_eq!(&buf[8..10], &[0x00, 0x52]);
+        }
+    }
+}
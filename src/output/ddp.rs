@@ -0,0 +1,113 @@
+//! DDP (Distributed Display Protocol) output, as spoken by WLED and others.
+//!
+//! Each frame is split across as many packets as it takes, the last one
+//! flagged to push the whole frame out at once. DDP has no timeout of its own,
+//! controllers fall back to their own effects after their configured timeout.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+use super::Output;
+use crate::colors::{channel_to_u8, Color};
+
+pub const PORT: u16 = 4048;
+/// Most data a packet carries, 480 RGB pixels.
+pub const MAX_DATA: usize = 1440;
+const HEADER_LEN: usize = 10;
+
+const FLAG_VERSION_1: u8 = 0x40;
+const FLAG_PUSH: u8 = 0x01;
+/// RGB, 8 bits per channel.
+const DATA_TYPE_RGB8: u8 = 0x0b;
+/// The display's default output.
+const DEST_DEFAULT: u8 = 1;
+
+/// Sends frames as DDP packets.
+pub struct DdpSender {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    /// 1-15, cycles once per frame.
+    sequence: u8,
+}
+
+impl DdpSender {
+    pub fn new(destination: SocketAddr) -> io::Result<Self> {
+        Ok(DdpSender {
+            socket: UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+            destination,
+            sequence: 1,
+        })
+    }
+}
+
+impl Output for DdpSender {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        let data: Vec<u8> = frame
+            .iter()
+            .flat_map(|c| [channel_to_u8(c.r), channel_to_u8(c.g), channel_to_u8(c.b)])
+            .collect();
+
+        let num_packets = data.len().div_ceil(MAX_DATA).max(1);
+        for i in 0..num_packets {
+            let offset = i * MAX_DATA;
+            let chunk = &data[offset..(offset + MAX_DATA).min(data.len())];
+            let push = i == num_packets - 1;
+            let packet = packet(self.sequence, offset as u32, chunk, push);
+            self.socket.send_to(&packet, self.destination)?;
+        }
+
+        self.sequence = self.sequence % 15 + 1;
+        Ok(())
+    }
+}
+
+/// Builds a packet carrying `data` starting `offset` bytes into the frame.
+pub fn packet(sequence: u8, offset: u32, data: &[u8], push: bool) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + data.len());
+    packet.push(if push {
+        FLAG_VERSION_1 | FLAG_PUSH
+    } else {
+        FLAG_VERSION_1
+    });
+    packet.push(sequence);
+    packet.push(DATA_TYPE_RGB8);
+    packet.push(DEST_DEFAULT);
+    packet.extend_from_slice(&offset.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn chunking() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut sender = DdpSender::new(receiver.local_addr().unwrap()).unwrap();
+
+        // 500 pixels is 480 in the first packet and 20 in the second
+        let mut frame = vec![Color::new(0., 0., 0., 1.); 500];
+        frame[480] = Color::new(1., 0.5, 0., 1.);
+        for sequence in 1..=2 {
+            sender.send_frame(&frame).unwrap();
+
+            let mut buf = [0; 2048];
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(len, HEADER_LEN + MAX_DATA);
+            assert_eq!(&buf[..4], &[0x40, sequence, 0x0b, 1]);
+            assert_eq!(&buf[4..10], &[0, 0, 0, 0, 0x05, 0xa0]);
+
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(len, HEADER_LEN + 60);
+            assert_eq!(&buf[..4], &[0x41, sequence, 0x0b, 1]);
+            assert_eq!(&buf[4..10], &[0, 0, 0x05, 0xa0, 0, 60]);
+            assert_eq!(&buf[10..13], &[255, 128, 0]);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Laying pixels out over DMX universes, shared by the DMX-over-IP outputs.
+
+use crate::colors::{channel_to_u8, Color};
+
+pub const CHANNELS: usize = 512;
+
+/// Lays `frame` out RGB across as many universes as it takes, returning the
+/// channel values for each.
+///
+/// The first universe starts `offset` channels in, the rest at channel 1.
+/// Pixels never straddle two universes, so a full one holds 170.
+pub fn universes(frame: &[Color], offset: usize) -> Vec<Vec<u8>> {
+    let mut universes = Vec::new();
+    let mut offset = offset;
+    let mut rest = frame;
+    while !rest.is_empty() {
+        let count = ((CHANNELS - offset) / 3).min(rest.len());
+        let mut channels = vec![0; offset];
+        for color in &rest[..count] {
+            channels.push(channel_to_u8(color.r));
+            channels.push(channel_to_u8(color.g));
+            channels.push(channel_to_u8(color.b));
+        }
+        universes.push(channels);
+
+        rest = &rest[count..];
+        offset = 0;
+    }
+    universes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let frame = vec![Color::new(1., 0., 0.5, 1.); 400];
+        let universes = universes(&frame, 10);
+
+        // (512 - 10) / 3 = 167, then 170, then the last 63
+        let lengths: Vec<usize> = universes.iter().map(Vec::len).collect();
+        assert_eq!(lengths, vec![10 + 167 * 3, 170 * 3, 63 * 3]);
+        assert!(universes[0][..10].iter().all(|c| *c == 0));
+        assert_eq!(&universes[0][10..13], &[255, 0, 128]);
+        assert_eq!(&universes[2][..3], &[255, 0, 128]);
+
+        assert!(super::universes(&[], 0).is_empty());
+    }
+}
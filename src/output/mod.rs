@@ -0,0 +1,382 @@
+//! Live outputs, for driving real LEDs as a pattern runs.
+//!
+//! Each output takes whole frames, one colour per tree pixel, and deals with
+//! getting them onto the wire however its protocol wants.
+//!
+//! On the command line an output is `kind[:address][;key=value...]`, e.g.
+//! `sacn`, `sacn:10.0.0.5;universe=3`, `artnet:10.0.0.255;net=1;sync=1` or
+//! `dnrgb:wled.local;timeout=5`, `opc:localhost` or
+//! `adalight:/dev/ttyUSB0;baud=500000`.
+//!
+//! Every output also takes the colour correction options, see
+//! [`crate::correction`]. Frames are corrected first and then power limited,
+//! so the limit holds for what actually reaches the LEDs.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod adalight;
+pub mod artnet;
+pub mod ddp;
+pub mod dmx;
+pub mod opc;
+pub mod sacn;
+pub mod wled;
+
+use crate::colors::Color;
+use crate::correction::Correction;
+use crate::patterns::{FrameContext, Pattern};
+use crate::power::{LimitedOutput, PowerModel};
+
+/// Somewhere frames can be sent to as they're generated.
+pub trait Output {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()>;
+}
+
+impl<O: Output + ?Sized> Output for Box<O> {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        (**self).send_frame(frame)
+    }
+}
+
+/// Corrects frames on their way to an output.
+pub struct Corrected<O: Output> {
+    output: O,
+    correction: Correction,
+}
+
+impl<O: Output> Corrected<O> {
+    /// The output corrected frames go to.
+    pub fn get_ref(&self) -> &O {
+        &self.output
+    }
+}
+
+impl<O: Output> Output for Corrected<O> {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        if self.correction.is_identity() {
+            return self.output.send_frame(frame);
+        }
+        let mut frame = frame.to_vec();
+        self.correction.apply_frame(&mut frame);
+        self.output.send_frame(&frame)
+    }
+}
+
+/// An opened output, with its own correction and power limiting.
+pub type OpenOutput = Corrected<LimitedOutput<Box<dyn Output>>>;
+
+/// Sends to every output in turn.
+impl<O: Output> Output for Vec<O> {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        for output in self.iter_mut() {
+            output.send_frame(frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// What an output sends to and how.
+#[derive(Clone, Debug)]
+pub enum OutputKind {
+    Sacn(sacn::SacnConfig),
+    ArtNet(artnet::ArtNetConfig),
+    Ddp(SocketAddr),
+    Wled(wled::WledConfig),
+    /// Server address and channel.
+    Opc(SocketAddr, u8),
+    Adalight(adalight::AdalightConfig),
+}
+
+/// An output as given on the command line, ready to be opened.
+#[derive(Clone, Debug)]
+pub struct OutputSpec {
+    pub kind: OutputKind,
+    pub correction: Correction,
+}
+
+impl OutputSpec {
+    /// Opens the output, limiting the current it draws to `power`.
+    pub fn open(&self, power: PowerModel) -> io::Result<OpenOutput> {
+        let output: Box<dyn Output> = match &self.kind {
+            OutputKind::Sacn(config) => Box::new(sacn::SacnSender::new(config.clone())?),
+            OutputKind::ArtNet(config) => Box::new(artnet::ArtNetSender::new(config.clone())?),
+            OutputKind::Ddp(destination) => Box::new(ddp::DdpSender::new(*destination)?),
+            OutputKind::Wled(config) => Box::new(wled::WledSender::new(config.clone())?),
+            OutputKind::Opc(address, channel) => {
+                Box::new(opc::OpcClient::connect(*address, *channel)?)
+            }
+            OutputKind::Adalight(config) => Box::new(adalight::AdalightSender::open(config)?),
+        };
+
+        Ok(Corrected {
+            output: LimitedOutput::new(output, power),
+            correction: self.correction.clone(),
+        })
+    }
+}
+
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(';');
+        let target = parts.next().unwrap_or("").trim();
+        let (name, address) = match target.split_once(':') {
+            Some((name, address)) => (name, Some(address)),
+            None => (target, None),
+        };
+        let mut options = Options::parse(parts)?;
+
+        let kind = match name {
+            "sacn" => OutputKind::Sacn(sacn::SacnConfig {
+                destination: match address {
+                    Some(address) => Some(parse_address(address, sacn::PORT)?),
+                    None => None,
+                },
+                start_universe: options.take("universe", 1)?,
+                start_channel: options.take("channel", 1)?,
+                priority: options.take("priority", 100)?,
+            }),
+            "artnet" => OutputKind::ArtNet(artnet::ArtNetConfig {
+                destination: required_address(name, address, artnet::PORT)?,
+                net: options.take("net", 0)?,
+                subnet: options.take("subnet", 0)?,
+                universe: options.take("universe", 0)?,
+                sync: options.take::<u8>("sync", 1)? != 0,
+            }),
+            "ddp" => OutputKind::Ddp(required_address(name, address, ddp::PORT)?),
+            "drgb" | "dnrgb" => OutputKind::Wled(wled::WledConfig {
+                destination: required_address(name, address, wled::PORT)?,
+                protocol: name.parse()?,
+                timeout: options.take("timeout", 2)?,
+            }),
+            "opc" => OutputKind::Opc(
+                required_address(name, address, opc::PORT)?,
+                options.take("channel", 0)?,
+            ),
+            "adalight" => OutputKind::Adalight(adalight::AdalightConfig {
+                path: match address {
+                    Some(path) if !path.is_empty() => String::from(path),
+                    _ => {
+                        return Err(String::from(
+                            "adalight needs a path, e.g. adalight:/dev/ttyUSB0",
+                        ))
+                    }
+                },
+                baud: options.take("baud", 115200)?,
+                framing: options.take("framing", adalight::Framing::default())?,
+            }),
+            _ => {
+                return Err(format!(
+                "unknown output \"{}\", expected sacn, artnet, ddp, drgb, dnrgb, opc or adalight",
+                name
+            ))
+            }
+        };
+
+        let correction = Correction::from_options(&mut options)?;
+        options.finish(&format!("{} output", name))?;
+        Ok(OutputSpec { kind, correction })
+    }
+}
+
+/// `key=value` options, checked off as they're used.
+pub struct Options<'a> {
+    values: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Options<'a> {
+    pub fn parse<I: Iterator<Item = &'a str>>(parts: I) -> Result<Self, String> {
+        let mut values = Vec::new();
+        for part in parts.map(str::trim).filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                Some((key, value)) => values.push((key.trim(), value.trim())),
+                None => return Err(format!("option \"{}\" is not key=value", part)),
+            }
+        }
+        Ok(Options { values })
+    }
+
+    /// Takes option `key` out as is, if it was given.
+    pub fn take_str(&mut self, key: &str) -> Option<&'a str> {
+        let i = self.values.iter().position(|(k, _)| *k == key)?;
+        Some(self.values.remove(i).1)
+    }
+
+    /// Takes option `key` out, or `default` if it wasn't given.
+    pub fn take<T: FromStr>(&mut self, key: &str, default: T) -> Result<T, String> {
+        match self.take_str(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("option {}=\"{}\" is invalid", key, value)),
+            None => Ok(default),
+        }
+    }
+
+    /// Errors on any options nothing took, `what` says what they were for.
+    pub fn finish(self, what: &str) -> Result<(), String> {
+        match self.values.first() {
+            Some((key, _)) => Err(format!("{} has no option \"{}\"", what, key)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses the address for outputs that can't do without one.
+fn required_address(
+    kind: &str,
+    address: Option<&str>,
+    default_port: u16,
+) -> Result<SocketAddr, String> {
+    match address {
+        Some(address) => parse_address(address, default_port),
+        None => Err(format!(
+            "{} needs an address, e.g. {}:192.168.1.50",
+            kind, kind
+        )),
+    }
+}
+
+/// Parses `host[:port]`, resolving host names if need be.
+pub fn parse_address(address: &str, default_port: u16) -> Result<SocketAddr, String> {
+    if let Ok(addr) = address.parse() {
+        return Ok(addr);
+    }
+    if let Ok(ip) = address.parse::<std::net::IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
+    }
+
+    let resolved = if address.contains(':') {
+        address.to_socket_addrs()
+    } else {
+        (address, default_port).to_socket_addrs()
+    };
+    match resolved.map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => Ok(addr),
+        _ => Err(format!("can't resolve output address \"{}\"", address)),
+    }
+}
+
+/// Runs `pattern` into `output` in real time at `fps`, until the pattern ends
+/// or `frame_limit` frames have been sent.
+///
+/// Frames are scheduled against the start time rather than the previous frame,
+/// so a slow frame doesn't push every later one back.
+pub fn stream(
+    pattern: &mut dyn Pattern,
+    output: &mut dyn Output,
+    fps: u32,
+    frame_limit: Option<usize>,
+) -> io::Result<()> {
+    if fps == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "fps must be at least 1",
+        ));
+    }
+
+    let start = Instant::now();
+    let mut i = 0;
+    while frame_limit.is_none_or(|limit| i < limit) {
+        let frame = match pattern.next_frame(&FrameContext::at_rate(i, fps)) {
+            Some(frame) => frame,
+            None => break,
+        };
+        output.send_frame(&frame)?;
+
+        i += 1;
+        let due = start + Duration::from_secs_f64(i as f64 / fps as f64);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limited_after_correction() {
+        let power = PowerModel {
+            limit: Some(10.),
+            ..Default::default()
+        };
+        let mut output = Corrected {
+            output: LimitedOutput::new(Vec::<Box<dyn Output>>::new(), power),
+            correction: "gamma=0.5".parse().unwrap(),
+        };
+
+        // Two 0.2 greens draw 10 mA, but gamma 0.5 brightens them to nearly 20
+        let green = Color::new(0., 0.2, 0., 1.);
+        output.send_frame(&[green, green]).unwrap();
+        let stats = &output.get_ref().stats;
+        assert!((stats.average() - 10.).abs() < 1e-3);
+        assert!(stats
+            .to_string()
+            .ends_with("1 of 1 frames limited to at most 0.01 A"));
+    }
+
+    #[test]
+    fn output_specs() {
+        let kind = |s: &str| s.parse::<OutputSpec>().map(|spec| spec.kind);
+
+        match kind("sacn") {
+            Ok(OutputKind::Sacn(config)) => {
+                assert_eq!(config.destination, None);
+                assert_eq!(config.start_universe, 1);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        match kind("sacn:10.0.0.5; universe=3;channel=4") {
+            Ok(OutputKind::Sacn(config)) => {
+                assert_eq!(config.destination, Some("10.0.0.5:5568".parse().unwrap()));
+                assert_eq!((config.start_universe, config.start_channel), (3, 4));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        match kind("artnet:10.0.0.255:6455;net=2;sync=0") {
+            Ok(OutputKind::ArtNet(config)) => {
+                assert_eq!(config.destination, "10.0.0.255:6455".parse().unwrap());
+                assert_eq!(config.net, 2);
+                assert!(!config.sync);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        match kind("dnrgb:127.0.0.1;timeout=255") {
+            Ok(OutputKind::Wled(config)) => {
+                assert_eq!(config.destination, "127.0.0.1:21324".parse().unwrap());
+                assert_eq!(config.protocol, wled::Protocol::Dnrgb);
+                assert_eq!(config.timeout, 255);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let spec: OutputSpec = "ddp:127.0.0.1;gamma=2.2;order=grb".parse().unwrap();
+        assert_eq!(spec.correction.curve, crate::correction::Curve::Gamma(2.2));
+        assert!(matches!(spec.kind, OutputKind::Ddp(_)));
+
+        for bad in [
+            "dmx",
+            "artnet",
+            "ddp",
+            "drgb:127.0.0.1;timeout=256",
+            "sacn;universe",
+            "sacn;universe=x",
+            "sacn;net=1",
+            "sacn;gamma=-1",
+        ] {
+            assert!(bad.parse::<OutputSpec>().is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Open Pixel Control, both ways.
+//!
+//! The client sends frames to an OPC server like a Fadecandy. The server
+//! takes frames from any OPC client and hands them out as a pattern, so
+//! patterns written in other languages can be watched on the 3D tree.
+//!
+//! Only "set pixel colours" messages are used, anything else is ignored.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::Output;
+use crate::colors::{channel_to_u8, Color};
+use crate::patterns::{FrameContext, Pattern};
+
+pub const PORT: u16 = 7890;
+const SET_PIXEL_COLORS: u8 = 0;
+/// Channel 0 is every channel on the server.
+const BROADCAST_CHANNEL: u8 = 0;
+
+/// Builds a "set pixel colours" message.
+pub fn message(channel: u8, frame: &[Color]) -> Vec<u8> {
+    // Length is 16 bits, anything past that doesn't fit in one message
+    let frame = &frame[..frame.len().min(u16::MAX as usize / 3)];
+    let mut message = vec![channel, SET_PIXEL_COLORS];
+    message.extend_from_slice(&(frame.len() as u16 * 3).to_be_bytes());
+    for color in frame {
+        message.push(channel_to_u8(color.r));
+        message.push(channel_to_u8(color.g));
+        message.push(channel_to_u8(color.b));
+    }
+    message
+}
+
+/// Sends frames to an OPC server over TCP.
+pub struct OpcClient {
+    stream: TcpStream,
+    channel: u8,
+}
+
+impl OpcClient {
+    pub fn connect(address: SocketAddr, channel: u8) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&address, Duration::from_secs(5))?;
+        // Frames are small and latency matters more than throughput
+        stream.set_nodelay(true)?;
+        // A stuck server shouldn't stall the viewer forever
+        stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+        Ok(OpcClient { stream, channel })
+    }
+}
+
+impl Output for OpcClient {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        self.stream.write_all(&message(self.channel, frame))
+    }
+}
+
+/// Accepts OPC clients in the background, keeping the latest frame any of
+/// them sent.
+#[cfg_attr(not(feature = "viewer"), allow(dead_code))]
+pub struct OpcServer {
+    latest: Arc<Mutex<Vec<Color>>>,
+    address: SocketAddr,
+}
+
+#[cfg_attr(not(feature = "viewer"), allow(dead_code))]
+impl OpcServer {
+    /// Starts listening for frames for a tree of `num_pixels`, on channel 0
+    /// or 1.
+    pub fn bind(address: SocketAddr, num_pixels: usize) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let latest = Arc::new(Mutex::new(vec![Color::new(0., 0., 0., 1.); num_pixels]));
+
+        let shared = latest.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = shared.clone();
+                // A client going away or sending garbage just ends its thread
+                thread::spawn(move || serve_client(stream, &shared, num_pixels));
+            }
+        });
+
+        Ok(OpcServer { latest, address })
+    }
+
+    /// Address actually listened on, handy when binding port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// A pattern that always shows the latest frame received.
+    pub fn frames(&self) -> ReceivedFrames {
+        ReceivedFrames {
+            latest: self.latest.clone(),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "viewer"), allow(dead_code))]
+fn serve_client(
+    mut stream: TcpStream,
+    latest: &Mutex<Vec<Color>>,
+    num_pixels: usize,
+) -> io::Result<()> {
+    let mut header = [0; 4];
+    let mut data = Vec::new();
+    loop {
+        stream.read_exact(&mut header)?;
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        data.resize(len, 0);
+        stream.read_exact(&mut data)?;
+
+        let [channel, command, ..] = header;
+        // Only the one strand, so only channel 1 or everything
+        if command != SET_PIXEL_COLORS || (channel != BROADCAST_CHANNEL && channel != 1) {
+            continue;
+        }
+
+        // Short frames leave the rest of the tree dark, long ones are cut off
+        let mut frame: Vec<Color> = data
+            .chunks_exact(3)
+            .take(num_pixels)
+            .map(|rgb| Color::from_rgba(rgb[0], rgb[1], rgb[2], 255))
+            .collect();
+        frame.resize(num_pixels, Color::new(0., 0., 0., 1.));
+        *latest.lock().unwrap() = frame;
+    }
+}
+
+/// Plays whatever an [`OpcServer`] last received, forever.
+#[cfg_attr(not(feature = "viewer"), allow(dead_code))]
+pub struct ReceivedFrames {
+    latest: Arc<Mutex<Vec<Color>>>,
+}
+
+impl Pattern for ReceivedFrames {
+    fn next_frame(&mut self, _ctx: &FrameContext) -> Option<Vec<Color>> {
+        Some(self.latest.lock().unwrap().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn message_layout() {
+        let frame = [Color::new(1., 0., 0.2, 1.), Color::new(0., 1., 0., 1.)];
+        assert_eq!(
+            message(BROADCAST_CHANNEL, &frame),
+            vec![0, 0, 0, 6, 255, 0, 51, 0, 255, 0]
+        );
+    }
+
+    #[test]
+    fn client_to_server() {
+        let server = OpcServer::bind("127.0.0.1:0".parse().unwrap(), 3).unwrap();
+        let mut frames = server.frames();
+        let mut client = OpcClient::connect(server.local_addr(), 1).unwrap();
+
+        // Ignored, wrong channel and then an unknown command
+        client
+            .stream
+            .write_all(&message(2, &[Color::new(1., 1., 1., 1.)]))
+            .unwrap();
+        client.stream.write_all(&[1, 255, 0, 2, 9, 9]).unwrap();
+        // Too short, the last pixel stays dark
+        let sent = [Color::new(1., 0., 0., 1.), Color::new(0., 0., 1., 1.)];
+        client.send_frame(&sent).unwrap();
+
+        let expected = vec![sent[0], sent[1], Color::new(0., 0., 0., 1.)];
+        let start = Instant::now();
+        loop {
+            let frame = frames.next_frame(&FrameContext::at_rate(0, 30)).unwrap();
+            if frame == expected {
+                break;
+            }
+            assert_eq!(frame, vec![Color::new(0., 0., 0., 1.); 3]);
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "frame never arrived"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Streaming ACN (E1.31) output.
+//!
+//! Pixels are laid out RGB across consecutive DMX universes, 170 to a
+//! universe so none straddle a boundary. Packets are multicast to each
+//! universe's group unless a unicast destination is given.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+use super::{dmx, Output};
+use crate::colors::Color;
+
+pub const PORT: u16 = 5568;
+const MAX_UNIVERSE: u16 = 63999;
+const SOURCE_NAME: &str = "xmas-mapper";
+
+const ACN_PACKET_ID: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x4;
+const VECTOR_E131_DATA_PACKET: u32 = 0x2;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x2;
+
+// Where each layer starts in a data packet
+const FRAMING_LAYER: usize = 38;
+const DMP_LAYER: usize = 115;
+const HEADER_LEN: usize = 126;
+
+#[derive(Clone, Debug)]
+pub struct SacnConfig {
+    /// Universe the first pixel goes in, 1-63999.
+    pub start_universe: u16,
+    /// 1-based DMX channel of the first pixel in the first universe.
+    pub start_channel: u16,
+    /// Unicast destination, multicast to each universe's group if `None`.
+    pub destination: Option<SocketAddr>,
+    /// 0-200, receivers take the highest priority source.
+    pub priority: u8,
+}
+
+impl Default for SacnConfig {
+    fn default() -> Self {
+        SacnConfig {
+            start_universe: 1,
+            start_channel: 1,
+            destination: None,
+            priority: 100,
+        }
+    }
+}
+
+/// Multicast group receivers of `universe` listen on.
+pub fn multicast_addr(universe: u16) -> SocketAddr {
+    let [hi, lo] = universe.to_be_bytes();
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, hi, lo)), PORT)
+}
+
+/// Sends frames as E1.31 data packets.
+pub struct SacnSender {
+    socket: UdpSocket,
+    config: SacnConfig,
+    /// Identifies this source to receivers, fresh every run.
+    cid: [u8; 16],
+    /// Next sequence number, per universe sent to.
+    sequences: Vec<u8>,
+}
+
+impl SacnSender {
+    pub fn new(config: SacnConfig) -> io::Result<Self> {
+        if !(1..=MAX_UNIVERSE).contains(&config.start_universe) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("sACN universes run from 1 to {}", MAX_UNIVERSE),
+            ));
+        }
+        // Needs room for at least one whole pixel
+        if !(1..=dmx::CHANNELS as u16 - 2).contains(&config.start_channel) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("start channel must be 1 to {}", dmx::CHANNELS - 2),
+            ));
+        }
+        if config.priority > 200 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sACN priority must be 0 to 200",
+            ));
+        }
+
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        Ok(SacnSender {
+            socket,
+            config,
+            cid: rand::random(),
+            sequences: Vec::new(),
+        })
+    }
+}
+
+impl Output for SacnSender {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        let universes = dmx::universes(frame, self.config.start_channel as usize - 1);
+        let last_universe = self.config.start_universe as usize + universes.len().saturating_sub(1);
+        if last_universe > MAX_UNIVERSE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} pixels need universes up to {}, past the last ({})",
+                    frame.len(),
+                    last_universe,
+                    MAX_UNIVERSE
+                ),
+            ));
+        }
+        self.sequences.resize(universes.len(), 0);
+
+        for (i, slots) in universes.iter().enumerate() {
+            let universe = self.config.start_universe + i as u16;
+            let packet = data_packet(
+                &self.cid,
+                self.config.priority,
+                universe,
+                self.sequences[i],
+                slots,
+            );
+            let destination = self
+                .config
+                .destination
+                .unwrap_or_else(|| multicast_addr(universe));
+            self.socket.send_to(&packet, destination)?;
+            self.sequences[i] = self.sequences[i].wrapping_add(1);
+        }
+
+        Ok(())
+    }
+}
+
+/// Flags and length field for a PDU starting at `start` in a packet of `len`.
+fn flags_and_length(len: usize, start: usize) -> [u8; 2] {
+    (0x7000 | (len - start) as u16).to_be_bytes()
+}
+
+/// Builds a data packet carrying `slots` (up to 512 DMX channels) for `universe`.
+pub fn data_packet(
+    cid: &[u8; 16],
+    priority: u8,
+    universe: u16,
+    sequence: u8,
+    slots: &[u8],
+) -> Vec<u8> {
+    let len = HEADER_LEN + slots.len();
+    let mut packet = Vec::with_capacity(len);
+
+    // Root layer
+    packet.extend_from_slice(&0x0010u16.to_be_bytes()); // Preamble size
+    packet.extend_from_slice(&0u16.to_be_bytes()); // Postamble size
+    packet.extend_from_slice(ACN_PACKET_ID);
+    packet.extend_from_slice(&flags_and_length(len, 16));
+    packet.extend_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+    packet.extend_from_slice(cid);
+
+    // Framing layer
+    packet.extend_from_slice(&flags_and_length(len, FRAMING_LAYER));
+    packet.extend_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+    let mut source_name = [0; 64];
+    source_name[..SOURCE_NAME.len()].copy_from_slice(SOURCE_NAME.as_bytes());
+    packet.extend_from_slice(&source_name);
+    packet.push(priority);
+    packet.extend_from_slice(&0u16.to_be_bytes()); // Sync address, unused
+    packet.push(sequence);
+    packet.push(0); // Options
+    packet.extend_from_slice(&universe.to_be_bytes());
+
+    // DMP layer
+    packet.extend_from_slice(&flags_and_length(len, DMP_LAYER));
+    packet.push(VECTOR_DMP_SET_PROPERTY);
+    packet.push(0xa1); // Address & data type
+    packet.extend_from_slice(&0u16.to_be_bytes()); // First property address
+    packet.extend_from_slice(&1u16.to_be_bytes()); // Address increment
+    packet.extend_from_slice(&(slots.len() as u16 + 1).to_be_bytes());
+    packet.push(0); // DMX start code
+    packet.extend_from_slice(slots);
+
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::channel_to_u8;
+    use crate::output::stream;
+    use crate::patterns::{self, PatternArgs};
+    use glam::vec3;
+    use std::time::Duration;
+
+    fn u16_at(packet: &[u8], i: usize) -> u16 {
+        u16::from_be_bytes([packet[i], packet[i + 1]])
+    }
+
+    #[test]
+    fn multicast_groups() {
+        assert_eq!(multicast_addr(1).to_string(), "239.255.0.1:5568");
+        assert_eq!(multicast_addr(63999).to_string(), "239.255.249.255:5568");
+    }
+
+    #[test]
+    fn loopback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // 169 pixels fit after channel 4, the other 31 spill into universe 8
+        let tree: Vec<_> = (0..200).map(|i| vec3(0., 0., i as f32 / 100.)).collect();
+        let config = SacnConfig {
+            start_universe: 7,
+            start_channel: 4,
+            destination: Some(receiver.local_addr().unwrap()),
+            priority: 150,
+        };
+        let mut sender = SacnSender::new(config).unwrap();
+
+        let info = patterns::find("rainbow").unwrap();
+        let args = PatternArgs::parse(info.params, None).unwrap();
+        let mut pattern = info.create(&tree, &args, 1);
+        stream(pattern.as_mut(), &mut sender, 1000, Some(3)).unwrap();
+
+        let mut expected = info.create(&tree, &args, 1);
+        let mut buf = [0; 1024];
+        for frame_index in 0..3 {
+            let frame = expected
+                .next_frame(&patterns::FrameContext::at_rate(frame_index, 1000))
+                .unwrap();
+
+            for (universe, offset, pixels) in [(7, 3, &frame[..169]), (8, 0, &frame[169..])] {
+                let len = receiver.recv(&mut buf).unwrap();
+                let packet = &buf[..len];
+                assert_eq!(len, HEADER_LEN + offset + pixels.len() * 3);
+
+                // Root layer
+                assert_eq!(u16_at(packet, 0), 0x0010);
+                assert_eq!(&packet[4..16], ACN_PACKET_ID);
+                assert_eq!(u16_at(packet, 16), 0x7000 | (len - 16) as u16);
+                assert_eq!(&packet[18..22], &[0, 0, 0, 4]);
+
+                // Framing layer
+                assert_eq!(u16_at(packet, 38), 0x7000 | (len - 38) as u16);
+                assert_eq!(&packet[40..44], &[0, 0, 0, 2]);
+                assert!(packet[44..108].starts_with(b"xmas-mapper\0"));
+                assert_eq!(packet[108], 150);
+                assert_eq!(packet[111], frame_index as u8);
+                assert_eq!(u16_at(packet, 113), universe);
+
+                // DMP layer
+                assert_eq!(u16_at(packet, 115), 0x7000 | (len - 115) as u16);
+                assert_eq!(&packet[117..123], &[0x02, 0xa1, 0, 0, 0, 1]);
+                assert_eq!(u16_at(packet, 123) as usize, len - 125);
+                assert_eq!(packet[125], 0);
+
+                let slots = &packet[HEADER_LEN..];
+                assert!(slots[..offset].iter().all(|s| *s == 0));
+                let colors: Vec<u8> = pixels
+                    .iter()
+                    .flat_map(|c| vec![c.r, c.g, c.b])
+                    .map(channel_to_u8)
+                    .collect();
+                assert_eq!(&slots[offset..], colors.as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn config_validation() {
+        let bad = [
+            SacnConfig {
+                start_universe: 0,
+                ..Default::default()
+            },
+            SacnConfig {
+                start_channel: 511,
+                ..Default::default()
+            },
+            SacnConfig {
+                priority: 201,
+                ..Default::default()
+            },
+        ];
+        for config in bad {
+            assert!(SacnSender::new(config).is_err());
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! WLED's own realtime UDP protocols, DRGB and DNRGB.
+//!
+//! DRGB fits a whole frame in one packet but tops out at 490 pixels. DNRGB
+//! carries a start index, so bigger trees get split over several packets.
+//! Every packet says how long the controller should wait without one before
+//! going back to its own effect.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+
+use super::Output;
+use crate::colors::{channel_to_u8, Color};
+
+pub const PORT: u16 = 21324;
+const DRGB_MAX_PIXELS: usize = 490;
+const DNRGB_MAX_PIXELS: usize = 489;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Drgb,
+    Dnrgb,
+}
+
+impl Protocol {
+    fn id(self) -> u8 {
+        match self {
+            Protocol::Drgb => 2,
+            Protocol::Dnrgb => 4,
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drgb" => Ok(Protocol::Drgb),
+            "dnrgb" => Ok(Protocol::Dnrgb),
+            _ => Err(format!(
+                "unknown WLED protocol \"{}\", expected drgb or dnrgb",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WledConfig {
+    pub destination: SocketAddr,
+    pub protocol: Protocol,
+    /// Seconds without a packet before the controller resumes its own effect,
+    /// 1-255 where 255 never does.
+    pub timeout: u8,
+}
+
+/// Sends frames with WLED's realtime protocols.
+pub struct WledSender {
+    socket: UdpSocket,
+    config: WledConfig,
+}
+
+impl WledSender {
+    pub fn new(config: WledConfig) -> io::Result<Self> {
+        // 0 tells WLED to leave realtime mode straight away
+        if config.timeout == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "WLED timeout must be 1-255 seconds",
+            ));
+        }
+
+        Ok(WledSender {
+            socket: UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+            config,
+        })
+    }
+}
+
+impl Output for WledSender {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        match self.config.protocol {
+            Protocol::Drgb => {
+                if frame.len() > DRGB_MAX_PIXELS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "DRGB only fits {} pixels, not {}, use dnrgb instead",
+                            DRGB_MAX_PIXELS,
+                            frame.len()
+                        ),
+                    ));
+                }
+                let packet = packet(Protocol::Drgb, self.config.timeout, 0, frame);
+                self.socket.send_to(&packet, self.config.destination)?;
+            }
+            Protocol::Dnrgb => {
+                for (i, chunk) in frame.chunks(DNRGB_MAX_PIXELS).enumerate() {
+                    let start = (i * DNRGB_MAX_PIXELS) as u16;
+                    let packet = packet(Protocol::Dnrgb, self.config.timeout, start, chunk);
+                    self.socket.send_to(&packet, self.config.destination)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds a packet of `pixels`, `start` is only used by DNRGB.
+pub fn packet(protocol: Protocol, timeout: u8, start: u16, pixels: &[Color]) -> Vec<u8> {
+    let mut packet = vec![protocol.id(), timeout];
+    if protocol == Protocol::Dnrgb {
+        packet.extend_from_slice(&start.to_be_bytes());
+    }
+    for color in pixels {
+        packet.push(channel_to_u8(color.r));
+        packet.push(channel_to_u8(color.g));
+        packet.push(channel_to_u8(color.b));
+    }
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn receiver() -> UdpSocket {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        receiver
+    }
+
+    #[test]
+    fn drgb() {
+        let receiver = receiver();
+        let mut sender = WledSender::new(WledConfig {
+            destination: receiver.local_addr().unwrap(),
+            protocol: Protocol::Drgb,
+            timeout: 2,
+        })
+        .unwrap();
+
+        let frame = vec![Color::new(1., 0., 0.2, 1.); 3];
+        sender.send_frame(&frame).unwrap();
+        let mut buf = [0; 2048];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[2, 2, 255, 0, 51, 255, 0, 51, 255, 0, 51]);
+
+        assert!(sender.send_frame(&vec![Color::default(); 491]).is_err());
+    }
+
+    #[test]
+    fn dnrgb_chunking() {
+        let receiver = receiver();
+        let mut sender = WledSender::new(WledConfig {
+            destination: receiver.local_addr().unwrap(),
+            protocol: Protocol::Dnrgb,
+            timeout: 255,
+        })
+        .unwrap();
+
+        let mut frame = vec![Color::default(); 1000];
+        frame[489] = Color::new(0., 1., 0., 1.);
+        sender.send_frame(&frame).unwrap();
+
+        let mut buf = [0; 2048];
+        for (start, pixels) in [(0u16, 489), (489, 489), (978, 22)] {
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(len, 4 + pixels * 3);
+            assert_eq!(&buf[..2], &[4, 255]);
+            assert_eq!(u16::from_be_bytes([buf[2], buf[3]]), start);
+            let first = if start == 489 { [0, 255, 0] } else { [0, 0, 0] };
+            assert_eq!(&buf[4..7], &first);
+        }
+
+        assert!(WledSender::new(WledConfig {
+            destination: receiver.local_addr().unwrap(),
+            protocol: Protocol::Dnrgb,
+            timeout: 0,
+        })
+        .is_err());
+    }
+}
@@ -1,15 +1,70 @@
-use std::collections::HashMap;
-use std::time::Instant;
+use super::{FrameContext, FromTree, ParamSpec, ParamValue, Pattern, PatternArgs, PatternRng};
+use crate::tree::{self, Pixel};
 
-use super::Pattern;
-use crate::tree::Pixel;
-
-use rand::random;
-use macroquad::color::*;
-use macroquad::prelude::{Vec3, vec3, Camera3D, set_camera, draw_sphere, next_frame, clear_background};
+use crate::colors::{Color, Oklab};
+use glam::{vec3, Vec3};
+use rand::Rng;
 
 use rand_distr::{Distribution, Normal};
 
+pub const PARAMS: &[ParamSpec] = &[
+    ParamSpec {
+        name: "num",
+        description: "Number of balls.",
+        default: ParamValue::Int(6),
+        min: ParamValue::Int(0),
+        max: ParamValue::Int(1000),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "avg_vel",
+        description: "Average starting speed of each axis (units/s).",
+        default: ParamValue::Float(0.5),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(10.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "rmean",
+        description: "Mean ball radius.",
+        default: ParamValue::Float(0.2),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(2.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "rdev",
+        description: "Standard deviation of ball radius.",
+        default: ParamValue::Float(0.05),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "restitution",
+        description: "Bounciness, 1 keeps every bit of speed and 0 doesn't bounce at all.",
+        default: ParamValue::Float(1.),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "gravity",
+        description: "Downwards acceleration (units/s^2), 0 to float.",
+        default: ParamValue::Float(0.),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(50.),
+        choices: &[],
+    },
+];
+
+/// OKLab lightness and chroma of every ball, bright and colourful without
+/// any of them washing out to white.
+const BALL_LIGHTNESS: f32 = 0.7;
+const BALL_CHROMA: f32 = 0.15;
+/// Radius the smallest ball is given, so none end up massless (or negative).
+const MIN_RADIUS: f32 = 0.01;
+
 /// Container for information pertaining to an individual ball.
 #[derive(Copy, Clone, Debug)]
 pub struct Ball {
@@ -17,7 +72,7 @@ pub struct Ball {
     vel: Vec3,
     color: Color,
     radius: f32,
-    // Not strictly necessary
+    /// Volume, balls are all equally dense.
     mass: f32,
 }
 
@@ -36,61 +91,82 @@ impl Ball {
         self.pos.distance(other.pos) <= (self.radius + other.radius)
     }
 
-    /// Applies perfectly elastic collision physics to both balls
+    /// Bounces two touching balls off each other.
+    ///
+    /// Only the velocity along the line between their centres changes, traded
+    /// between them by mass. `restitution` is how much of their approach speed
+    /// they separate with, 1.0 being perfectly elastic and conserving kinetic
+    /// energy, 0.0 leaving them moving together. Momentum is always conserved.
     ///
-    /// Math stolen from: https://atmos.illinois.edu/courses/atmos100/userdocs/3Dcollisions.html
-    /// Which is actually really bad.
-    pub fn collision(&mut self, other: &mut Self) {
-        // Step 1: calculate the 3d angle between colliders
-        // This uses dot product
-        let delta_1 = (other.pos - self.pos).normalize();
-        let angle_1 = self.vel.angle_between(delta_1);
-        let delta_2 = (self.pos - other.pos).normalize();
-        let angle_2 = other.vel.angle_between(delta_2);
-
-        // Step 2: calculate colliders force vectors towards each other
-        // (There is an optimization step here, previous angle could skip
-        // an acos, and this could skip the cos)
-        let v_center_1_i = self.vel.length() * f32::cos(angle_1);
-        let v_center_2_i = other.vel.length() * f32::cos(angle_2);
-
-        // Check if they are moving towards or away from each other.
-        if v_center_1_i + v_center_2_i <= 0. {
-            // They are currently intersecting, but moving away from each other,
-            // don't apply collision.
+    /// Overlapping balls are also pushed apart so they only just touch, the
+    /// lighter one moving further, so they can't end up stuck inside each other.
+    pub fn collision(&mut self, other: &mut Self, restitution: f32) {
+        let delta = other.pos - self.pos;
+        let distance = delta.length();
+        // Dead centre on each other has no direction, any will do
+        let normal = if distance > f32::EPSILON {
+            delta / distance
+        } else {
+            Vec3::X
+        };
+
+        let inv_mass_self = 1. / self.mass;
+        let inv_mass_other = 1. / other.mass;
+        let inv_mass_sum = inv_mass_self + inv_mass_other;
+
+        let overlap = self.radius + other.radius - distance;
+        if overlap > 0. {
+            self.pos -= normal * (overlap * inv_mass_self / inv_mass_sum);
+            other.pos += normal * (overlap * inv_mass_other / inv_mass_sum);
+        }
+
+        // Already separating, leave them be
+        let approach = (self.vel - other.vel).dot(normal);
+        if approach <= 0. {
             return;
         }
 
-        // Step 4: determine the force vector normal to the center-line
-        // to  recompose new collision
-        let normal_1 = self.vel - v_center_1_i * delta_1;
-        let normal_2 = other.vel - v_center_2_i * delta_2;
-
-        // Step 5: Switch the collider's force vectors (modified for 1-D differing masses)
-        //let v_center_1_f = v_center_2_i;
-        //let v_center_2_f = v_center_1_i;
-        let mass_sum = self.mass + other.mass;
-        let v_center_1_f = (self.mass - other.mass) / mass_sum * v_center_1_i
-            + 2. * other.mass / mass_sum * v_center_2_i;
-        let v_center_2_f = 2. * self.mass / mass_sum * v_center_1_i
-            + (other.mass - self.mass) / mass_sum * v_center_2_i;
-
-        // Step 6: Compose vectors into new velocity
-        // THIS IS WRONG
-        self.vel = v_center_1_f * delta_2 + normal_1;
-        other.vel = v_center_2_f * delta_1 + normal_2;
+        let impulse = (1. + restitution) * approach / inv_mass_sum;
+        self.vel -= normal * (impulse * inv_mass_self);
+        other.vel += normal * (impulse * inv_mass_other);
+    }
+
+    /// Momentum, mass times velocity.
+    #[cfg(test)]
+    fn momentum(&self) -> Vec3 {
+        self.vel * self.mass
     }
 
-    /// Update position based on current velocity.
-    pub fn update(&mut self) {
-        // TODO programmatically set this via frame rate
-        self.pos += self.vel / 30.;
+    #[cfg(test)]
+    fn kinetic_energy(&self) -> f32 {
+        0.5 * self.mass * self.vel.length_squared()
+    }
+
+    /// Update velocity and position, `dt` seconds on under `gravity`.
+    pub fn update(&mut self, dt: f32, gravity: f32) {
+        self.vel.z -= gravity * dt;
+        self.pos += self.vel * dt;
+    }
+}
+
+/// Keeps one axis of a ball's centre within `min..=max`, sending it back the
+/// other way if it was heading out.
+fn bounce_off_walls(pos: &mut f32, vel: &mut f32, min: f32, max: f32, restitution: f32) {
+    if *pos < min {
+        *pos = min;
+        if *vel < 0. {
+            *vel *= -restitution;
+        }
+    } else if *pos > max {
+        *pos = max;
+        if *vel > 0. {
+            *vel *= -restitution;
+        }
     }
 }
 
 /// Creates a bunch of balls that float around the bounding box of the tree
-/// and collide elastically with each other. (Potentially transferring colour
-/// as well.)
+/// and collide with each other. (Potentially transferring colour as well.)
 pub struct BallPattern {
     /// Clone of tree structure. This never changes so a clone is fine.
     tree: Vec<Pixel>,
@@ -102,54 +178,69 @@ pub struct BallPattern {
     zlim_max: f32,
     // Minimum bound of Z height (floor)
     zlim_min: f32,
+
+    restitution: f32,
+    gravity: f32,
+
+    /// Balls sorted for collision checks, see [`update_ball_collisions`].
+    sweep_order: Vec<usize>,
 }
 
-pub fn update_ball_collisions(mut ball: usize, balls: &mut Vec<Ball>) {
-    if ball >= balls.len() {
-        return;
+/// Bounces every pair of touching balls off each other.
+///
+/// `order` is scratch space kept between frames, balls barely move from one
+/// frame to the next so it's already nearly sorted.
+pub fn update_ball_collisions(balls: &mut [Ball], order: &mut Vec<usize>, restitution: f32) {
+    for (i, j) in touching_pairs(balls, order) {
+        let (a, b) = pair_mut(balls, i, j);
+        // An earlier collision this frame may have pushed them apart already
+        if a.collides(b) {
+            a.collision(b, restitution);
+        }
     }
+}
 
-    // Check for inter-ball collisions
-    // TODO surely there must be a nice iterator way to do this
-    for i in ball..balls.len() {
-        for j in (i + 1)..balls.len() {
-            // SAFETY: The correct bounds are being used and I am only
-            // accessing them in each iter loop. Also they aren't ever
-            // accessing the same element.
-            unsafe {
-                // TODO actually understand this syntax
-                let a = &mut *(balls.get_unchecked_mut(i) as *mut _);
-                let b = &mut *(balls.get_unchecked_mut(j) as *mut _);
-                if Ball::collides(a, b) {
-                    Ball::collision(a, b);
-                }
+/// Every pair of touching balls, lowest index first.
+///
+/// Sweep and prune along x: with the balls sorted by where they start, each
+/// can only touch those after it that start before it ends, so most pairs are
+/// never looked at.
+fn touching_pairs(balls: &[Ball], order: &mut Vec<usize>) -> Vec<(usize, usize)> {
+    if order.len() != balls.len() {
+        *order = (0..balls.len()).collect();
+    }
+    let start = |i: usize| balls[i].pos.x - balls[i].radius;
+    // Stable sort, which is close to linear on the nearly sorted order
+    order.sort_by(|&a, &b| start(a).total_cmp(&start(b)));
+
+    let mut pairs = Vec::new();
+    for (n, &i) in order.iter().enumerate() {
+        let end = balls[i].pos.x + balls[i].radius;
+        for &j in &order[n + 1..] {
+            if start(j) > end {
+                break;
+            }
+            if balls[i].collides(&balls[j]) {
+                pairs.push((i.min(j), i.max(j)));
             }
         }
     }
+    pairs
+}
 
-    ball += 1;
-    update_ball_collisions(ball, balls);
+/// Mutable references to two different balls at once.
+fn pair_mut(balls: &mut [Ball], i: usize, j: usize) -> (&mut Ball, &mut Ball) {
+    assert!(i < j, "ball pair must be in order");
+    let (head, tail) = balls.split_at_mut(j);
+    (&mut head[i], &mut tail[0])
 }
 
-impl Pattern for BallPattern {
-    fn from_tree(tree: &Vec<Pixel>, args: &HashMap<String, String>) -> Self {
-        // Parameters that will be turned into arguments eventully.
-        let num_balls: u8 = match args.get("num") {
-            Some(num) => num.as_str().parse().unwrap(),
-            None => 6,
-        };
-        let avg_start_vel: f32 = match args.get("avg_vel") {
-            Some(vel) => vel.as_str().parse().unwrap(),
-            None => 0.5,
-        };
-        let radius_mean: f32 = match args.get("rmean") {
-            Some(mean) => mean.as_str().parse().unwrap(),
-            None => 0.2,
-        };
-        let radius_deviation: f32 = match args.get("rdev") {
-            Some(dev) => dev.as_str().parse().unwrap(),
-            None => 0.05,
-        };
+impl FromTree for BallPattern {
+    fn from_tree(tree: &[Pixel], args: &PatternArgs, mut rng: PatternRng) -> Self {
+        let num_balls = args.int("num");
+        let avg_start_vel = args.float("avg_vel");
+        let radius_mean = args.float("rmean");
+        let radius_deviation = args.float("rdev");
         // Not sure if I want maximum velocity here
         // This will need to be taken into account in the collision code.
         // Maybe needs to be maximum momentum?
@@ -160,52 +251,42 @@ impl Pattern for BallPattern {
 
         // Find ceiling of tree (idk if I care about the floor, I can add it
         // later if I want)
-        let mut zlim_max: f32 = 0.;
-        for pixel in tree {
-            // NOTE: Assumes no inf/-inf or NaN.
-            if pixel.z > zlim_max {
-                zlim_max = pixel.z;
-            }
-        }
+        let (_, max) = tree::bounds(tree);
+        let zlim_max = max.z.max(0.);
 
         let mut balls = Vec::new();
         for _ in 0..num_balls {
-            let x = random::<f32>() * 2.0 - 1.0;
-            let y = random::<f32>() * 2.0 - 1.0;
-            let z = random::<f32>() * zlim_max;
-            let vel_x = (random::<f32>() - 0.5) * avg_start_vel * 2.0;
-            let vel_y = (random::<f32>() - 0.5) * avg_start_vel * 2.0;
-            let vel_z = (random::<f32>() - 0.5) * avg_start_vel * 2.0;
+            let x = rng.gen::<f32>() * 2.0 - 1.0;
+            let y = rng.gen::<f32>() * 2.0 - 1.0;
+            let z = rng.gen::<f32>() * zlim_max;
+            let vel_x = (rng.gen::<f32>() - 0.5) * avg_start_vel * 2.0;
+            let vel_y = (rng.gen::<f32>() - 0.5) * avg_start_vel * 2.0;
+            let vel_z = (rng.gen::<f32>() - 0.5) * avg_start_vel * 2.0;
             let pos = vec3(x, y, z);
             let vel = vec3(vel_x, vel_y, vel_z);
 
-            // Generate random colour
-            let r = random::<f32>();
-            let g = random::<f32>();
-            let b = random::<f32>();
-            // Normalize to 50% intensity
-            // TODO parameterize this intensity
-            // FIXME sometimes generates black colour
-            let intensity = (r + g + b) / 3.;
-            let intensity_ratio = intensity / 0.5;
-            let r = (r * intensity_ratio * 255.) as u8;
-            let g = (g * intensity_ratio * 255.) as u8;
-            let b = (b * intensity_ratio * 255.) as u8;
-            let color = Color::from_rgba(r, g, b, 255);
-
-            let radius = radius_distr.sample(&mut rand::thread_rng());
+            // Random hue, all equally bright to the eye
+            let color =
+                Oklab::from_lch(BALL_LIGHTNESS, BALL_CHROMA, rng.gen::<f32>() * 360.).to_color();
+
+            let radius = radius_distr.sample(&mut rng).max(MIN_RADIUS);
             balls.push(Ball::new(pos, vel, color, radius));
         }
 
         BallPattern {
-            tree: (*tree).clone(),
+            tree: tree.to_vec(),
             balls,
             zlim_max,
             zlim_min: 0.,
+            restitution: args.float("restitution"),
+            gravity: args.float("gravity"),
+            sweep_order: Vec::new(),
         }
     }
+}
 
-    fn next_frame(&mut self) -> Option<Vec<Color>> {
+impl Pattern for BallPattern {
+    fn next_frame(&mut self, ctx: &FrameContext) -> Option<Vec<Color>> {
         // "Render" based on initial positions
         let mut frame = Vec::new();
         for pixel in &self.tree {
@@ -219,91 +300,242 @@ impl Pattern for BallPattern {
             frame.push(color);
         }
 
-        update_ball_collisions(0, &mut self.balls);
+        update_ball_collisions(&mut self.balls, &mut self.sweep_order, self.restitution);
 
-        // Check for wall collisions
         for ball in &mut self.balls {
-            if ball.pos.x < -1.0 && ball.vel.x < 0. {
-                ball.vel.x = -ball.vel.x;
-            } else if ball.pos.x > 1.0 && ball.vel.x > 0. {
-                ball.vel.x = -ball.vel.x;
-            }
+            ball.update(ctx.dt, self.gravity);
+
+            // Bounce off the walls, putting back anything that went through so
+            // gravity can't drag it out through the floor
+            bounce_off_walls(&mut ball.pos.x, &mut ball.vel.x, -1., 1., self.restitution);
+            bounce_off_walls(&mut ball.pos.y, &mut ball.vel.y, -1., 1., self.restitution);
+            bounce_off_walls(
+                &mut ball.pos.z,
+                &mut ball.vel.z,
+                self.zlim_min,
+                self.zlim_max,
+                self.restitution,
+            );
+        }
 
-            if ball.pos.y < -1.0 && ball.vel.y < 0. {
-                ball.vel.y = -ball.vel.y;
-            } else if ball.pos.y > 1.0 && ball.vel.y > 0. {
-                ball.vel.y = -ball.vel.y;
-            }
+        Some(frame)
+    }
+}
 
-            if ball.pos.z < self.zlim_min && ball.vel.z < 0. {
-                ball.vel.z = -ball.vel.z;
-            } else if ball.pos.z > self.zlim_max && ball.vel.z > 0. {
-                ball.vel.z = -ball.vel.z;
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::time::Instant;
+
+    /// Two random balls, overlapping and with random velocities.
+    fn random_pair(rng: &mut PatternRng) -> (Ball, Ball) {
+        let mut random_vec = || vec3(rng.gen(), rng.gen(), rng.gen()) * 2. - Vec3::ONE;
+        let (pos, offset) = (random_vec(), random_vec());
+        let (vel_a, vel_b) = (random_vec() * 3., random_vec() * 3.);
+        let radius_a = rng.gen_range(0.05..0.5);
+        let radius_b = rng.gen_range(0.05..0.5);
+        let color = Color::default();
+
+        let a = Ball::new(pos, vel_a, color, radius_a);
+        // Somewhere from dead centre to just touching
+        let distance = rng.gen_range(0.0..1.0) * (radius_a + radius_b);
+        let b = Ball::new(pos + offset.normalize() * distance, vel_b, color, radius_b);
+        (a, b)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-3 * a.abs().max(b.abs()).max(1e-3)
+    }
 
-            // Update positions while we're here
-            ball.update();
+    #[test]
+    fn elastic_conserves_momentum_and_energy() {
+        let mut rng = PatternRng::seed_from_u64(1);
+        for _ in 0..10_000 {
+            let (mut a, mut b) = random_pair(&mut rng);
+            let momentum = a.momentum() + b.momentum();
+            let energy = a.kinetic_energy() + b.kinetic_energy();
+
+            a.collision(&mut b, 1.);
+
+            let after = a.momentum() + b.momentum();
+            assert!(close(after.x, momentum.x), "{} {}", after.x, momentum.x);
+            assert!(close(after.y, momentum.y), "{} {}", after.y, momentum.y);
+            assert!(close(after.z, momentum.z), "{} {}", after.z, momentum.z);
+            let after = a.kinetic_energy() + b.kinetic_energy();
+            assert!(close(after, energy), "{} {}", after, energy);
+
+            // Left just touching and moving apart, so they don't collide again
+            let distance = a.pos.distance(b.pos);
+            assert!(distance >= (a.radius + b.radius) * (1. - 1e-4));
+            assert!((b.vel - a.vel).dot(b.pos - a.pos) >= -1e-4);
         }
+    }
 
-        Some(frame)
+    #[test]
+    fn inelastic_loses_energy_not_momentum() {
+        let mut rng = PatternRng::seed_from_u64(2);
+        for _ in 0..10_000 {
+            let (mut a, mut b) = random_pair(&mut rng);
+            let restitution = rng.gen_range(0.0..1.0);
+            let momentum = a.momentum() + b.momentum();
+            let energy = a.kinetic_energy() + b.kinetic_energy();
+
+            a.collision(&mut b, restitution);
+
+            let after = a.momentum() + b.momentum();
+            assert!(close(after.x, momentum.x), "{} {}", after.x, momentum.x);
+            assert!(close(after.y, momentum.y), "{} {}", after.y, momentum.y);
+            assert!(close(after.z, momentum.z), "{} {}", after.z, momentum.z);
+            assert!(a.kinetic_energy() + b.kinetic_energy() <= energy * (1. + 1e-4));
+        }
     }
-}
 
-pub async fn run_ball_loop(mut pattern: BallPattern, rpm: u32, fps: u32) {
-    // Pre-calculate rotational velocity of scene
-    let rot_vel: f32 = std::f32::consts::PI * 2. * (rpm as f32 / 60.);
-    // Too lazy to do fixed-point math
-
-    // Prep rotation
-    let mut prev_frame_time = Instant::now();
-    let mut theta: f32 = 0.;
-
-    loop {
-        // Set up basic scene
-        clear_background(DARKGRAY);
-
-        let frame_time = Instant::now();
-        let delta = frame_time - prev_frame_time;
-        prev_frame_time = frame_time;
-
-        // Set up camera
-        theta += (delta.as_millis() as f32) / 1000. * rot_vel; // Update camera angle
-        set_camera(&Camera3D {
-            position: vec3(theta.sin() * 4., theta.cos() * 4., 3.),
-            target: vec3(0., 0., 1.5),
-            up: vec3(0., 0., 1.),
-            ..Default::default()
-        });
-        // Check for inter-ball collisions
-        // TODO surely there must be a nice iterator way to do this
-        update_ball_collisions(0, &mut pattern.balls);
-        // Check for wall collisions
-        for ball in &mut pattern.balls {
-            if ball.pos.x < -1.0 && ball.vel.x < 0. {
-                ball.vel.x = -ball.vel.x;
-            } else if ball.pos.x > 1.0 && ball.vel.x > 0. {
-                ball.vel.x = -ball.vel.x;
+    #[test]
+    fn head_on() {
+        let color = Color::default();
+        // Equal masses swap velocities
+        let mut a = Ball::new(vec3(0., 0., 0.), vec3(1., 0., 0.), color, 0.1);
+        let mut b = Ball::new(vec3(0.2, 0., 0.), vec3(-0.5, 0., 0.), color, 0.1);
+        a.collision(&mut b, 1.);
+        assert!((a.vel - vec3(-0.5, 0., 0.)).length() < 1e-5);
+        assert!((b.vel - vec3(1., 0., 0.)).length() < 1e-5);
+
+        // Perfectly inelastic, they carry on together
+        let mut a = Ball::new(vec3(0., 0., 0.), vec3(1., 0., 0.), color, 0.1);
+        let mut b = Ball::new(vec3(0.15, 0., 0.), vec3(-1., 0., 0.), color, 0.1);
+        a.collision(&mut b, 0.);
+        assert!(a.vel.length() < 1e-5 && b.vel.length() < 1e-5);
+        // And were pushed out of each other evenly
+        assert!((a.pos.x + 0.025).abs() < 1e-5 && (b.pos.x - 0.175).abs() < 1e-5);
+    }
+
+    #[test]
+    fn seeded() {
+        let tree = [vec3(-1., -1., 0.), vec3(0., 0.5, 1.), vec3(1., 1., 2.)];
+        let info = crate::patterns::find("balls").unwrap();
+        let args = PatternArgs::parse(PARAMS, Some("rmean=0.5")).unwrap();
+        let frames = |seed| {
+            let mut pattern = info.create(&tree, &args, seed);
+            (0..30)
+                .map(|i| pattern.next_frame(&FrameContext::at_rate(i, 30)).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(frames(7), frames(7));
+        assert_ne!(frames(7), frames(8));
+    }
+
+    #[test]
+    fn gravity_stays_in_box() {
+        let tree = [vec3(-1., -1., 0.), vec3(1., 1., 2.)];
+        for restitution in ["0", "0.5", "0.9", "1"] {
+            let args = format!("num=50;gravity=9.8;restitution={}", restitution);
+            let args = PatternArgs::parse(PARAMS, Some(&args)).unwrap();
+            let mut pattern = BallPattern::from_tree(&tree, &args, PatternRng::seed_from_u64(3));
+            for i in 0..3000 {
+                pattern.next_frame(&FrameContext::at_rate(i, 30));
+                for ball in &pattern.balls {
+                    assert!(
+                        ball.pos.x.abs() <= 1. && ball.pos.y.abs() <= 1.,
+                        "{:?} at restitution {}",
+                        ball.pos,
+                        restitution
+                    );
+                    assert!(
+                        (0. ..=2.).contains(&ball.pos.z),
+                        "{:?} at restitution {}",
+                        ball.pos,
+                        restitution
+                    );
+                }
             }
+        }
+    }
 
-            if ball.pos.y < -1.0 && ball.vel.y < 0. {
-                ball.vel.y = -ball.vel.y;
-            } else if ball.pos.y > 1.0 && ball.vel.y > 0. {
-                ball.vel.y = -ball.vel.y;
+    /// `num` balls of `radius` spread through the bounding box.
+    fn random_balls(rng: &mut PatternRng, num: usize, radius: f32) -> Vec<Ball> {
+        (0..num)
+            .map(|_| {
+                let pos = vec3(rng.gen(), rng.gen(), rng.gen()) * 2. - Vec3::ONE;
+                let vel = vec3(rng.gen(), rng.gen(), rng.gen()) - Vec3::splat(0.5);
+                let radius = rng.gen_range(0.5..1.5) * radius;
+                Ball::new(pos, vel, Color::default(), radius)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn broad_phase_finds_every_collision() {
+        let mut rng = PatternRng::seed_from_u64(3);
+        let mut order = Vec::new();
+        for num in [0, 1, 2, 50, 300] {
+            let balls = random_balls(&mut rng, num, 0.1);
+            let mut expected = Vec::new();
+            for i in 0..num {
+                for j in (i + 1)..num {
+                    if balls[i].collides(&balls[j]) {
+                        expected.push((i, j));
+                    }
+                }
             }
 
-            if ball.pos.z < pattern.zlim_min && ball.vel.z < 0. {
-                ball.vel.z = -ball.vel.z;
-            } else if ball.pos.z > pattern.zlim_max && ball.vel.z > 0. {
-                ball.vel.z = -ball.vel.z;
+            let mut pairs = touching_pairs(&balls, &mut order);
+            pairs.sort_unstable();
+            assert_eq!(pairs, expected);
+        }
+    }
+
+    /// The recursive collision loop this replaced, kept to benchmark against.
+    fn update_ball_collisions_recursive(mut ball: usize, balls: &mut [Ball], restitution: f32) {
+        if ball >= balls.len() {
+            return;
+        }
+
+        for i in ball..balls.len() {
+            for j in (i + 1)..balls.len() {
+                let (a, b) = pair_mut(balls, i, j);
+                if Ball::collides(a, b) {
+                    Ball::collision(a, b, restitution);
+                }
             }
+        }
 
-            // Update positions while we're here
-            ball.update();
+        ball += 1;
+        update_ball_collisions_recursive(ball, balls, restitution);
+    }
+
+    /// Compares the old and new collision loops, run with
+    /// `cargo test --release --no-default-features collision_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn collision_benchmark() {
+        const FRAMES: usize = 30;
+
+        fn time_frames<F: FnMut(&mut [Ball])>(balls: &[Ball], mut collide: F) -> f64 {
+            let mut balls = balls.to_vec();
+            let start = Instant::now();
+            for _ in 0..FRAMES {
+                collide(&mut balls);
+                for ball in &mut balls {
+                    ball.update(1. / 30., 0.);
+                }
+            }
+            start.elapsed().as_secs_f64() * 1000. / FRAMES as f64
         }
 
-        for ball in &pattern.balls {
-            draw_sphere(ball.pos, ball.radius, None, ball.color);
+        let mut rng = PatternRng::seed_from_u64(4);
+        println!("{:>6} {:>14} {:>14}", "balls", "recursive ms", "sweep ms");
+        for num in [10, 50, 100, 200, 400] {
+            let balls = random_balls(&mut rng, num, 0.05);
+            let recursive = time_frames(&balls, |balls| {
+                update_ball_collisions_recursive(0, balls, 1.)
+            });
+            let mut order = Vec::new();
+            let sweep = time_frames(&balls, |balls| {
+                update_ball_collisions(balls, &mut order, 1.)
+            });
+            println!("{:>6} {:>14.3} {:>14.3}", num, recursive, sweep);
         }
-        next_frame().await;
     }
 }
@@ -1,14 +1,13 @@
-use super::Pattern;
+use super::{FrameContext, FromTree, Pattern, PatternArgs, PatternRng};
+use crate::colors::Color;
 use crate::tree::Pixel;
-use macroquad::color::Color;
-use std::collections::HashMap;
 
 pub struct Green {
     storage: Vec<Color>,
 }
 
-impl Pattern for Green {
-    fn from_tree(tree: &Vec<Pixel>, _args: &HashMap<String, String>) -> Self {
+impl FromTree for Green {
+    fn from_tree(tree: &[Pixel], _args: &PatternArgs, _rng: PatternRng) -> Self {
         // TODO do the iter way
         let mut storage = Vec::new();
         for _ in tree {
@@ -17,8 +16,10 @@ impl Pattern for Green {
 
         Self { storage }
     }
+}
 
-    fn next_frame(&mut self) -> Option<Vec<Color>> {
+impl Pattern for Green {
+    fn next_frame(&mut self, _ctx: &FrameContext) -> Option<Vec<Color>> {
         Some(self.storage.clone())
     }
 }
@@ -1,14 +1,158 @@
 use crate::tree::Pixel;
-use std::collections::HashMap;
 
-use macroquad::color::Color;
+use crate::colors::Color;
+use rand::SeedableRng;
 
 pub mod balls;
 pub mod green;
+pub mod params;
 pub mod rainbow;
+pub mod spatial_rainbow;
+pub mod sweep;
+
+pub use params::{ParamSpec, ParamValue, PatternArgs};
+
+/// RNG handed to patterns on construction. ChaCha's output is stable across
+/// platforms and releases, so a seed always reproduces the same animation.
+pub type PatternRng = rand_chacha::ChaCha8Rng;
+
+/// Timing of the frame being generated.
+///
+/// Patterns should move by `dt`/`time` rather than per call, so an animation
+/// looks the same in real time at any frame rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameContext {
+    /// Frames since the pattern started, the first frame is 0.
+    pub index: usize,
+    /// Seconds between this frame and the last.
+    pub dt: f32,
+    /// Seconds since the first frame.
+    pub time: f32,
+}
+
+impl FrameContext {
+    /// Timing of frame `index` when running at a steady `fps`.
+    pub fn at_rate(index: usize, fps: u32) -> Self {
+        let dt = 1. / fps as f32;
+        FrameContext {
+            index,
+            dt,
+            time: index as f32 * dt,
+        }
+    }
+}
 
 pub trait Pattern {
-    fn from_tree(tree: &Vec<Pixel>, args: &HashMap<String, String>) -> Self;
+    fn next_frame(&mut self, ctx: &FrameContext) -> Option<Vec<Color>>;
+}
+
+/// Patterns that are generated from a tree's layout, as opposed to played back
+/// from somewhere else.
+pub trait FromTree: Pattern {
+    /// Any randomness must come from `rng`, so exports are reproducible.
+    fn from_tree(tree: &[Pixel], args: &PatternArgs, rng: PatternRng) -> Self;
+}
+
+/// Builds a boxed pattern, so every pattern can sit behind the same constructor type.
+pub type PatternConstructor = fn(&[Pixel], &PatternArgs, PatternRng) -> Box<dyn Pattern>;
+
+/// Registry entry describing a selectable pattern.
+pub struct PatternInfo {
+    /// Name used to select the pattern from the CLI.
+    pub name: &'static str,
+    /// One-line description shown by `list-patterns`.
+    pub description: &'static str,
+    /// Arguments accepted through `--pattern-args`.
+    pub params: &'static [ParamSpec],
+    constructor: PatternConstructor,
+}
+
+impl PatternInfo {
+    /// Builds the pattern. The same seed always gives the same frames.
+    pub fn create(&self, tree: &[Pixel], args: &PatternArgs, seed: u64) -> Box<dyn Pattern> {
+        (self.constructor)(tree, args, PatternRng::seed_from_u64(seed))
+    }
+}
+
+fn construct<T: FromTree + 'static>(
+    tree: &[Pixel],
+    args: &PatternArgs,
+    rng: PatternRng,
+) -> Box<dyn Pattern> {
+    Box::new(T::from_tree(tree, args, rng))
+}
+
+/// Every pattern that can be selected at runtime.
+pub const PATTERNS: &[PatternInfo] = &[
+    PatternInfo {
+        name: "green",
+        description: "Lights up every light green.",
+        params: &[],
+        constructor: construct::<green::Green>,
+    },
+    PatternInfo {
+        name: "rainbow",
+        description: "Chases rainbows along the string, ignoring layout.",
+        params: rainbow::PARAMS,
+        constructor: construct::<rainbow::Rainbow>,
+    },
+    PatternInfo {
+        name: "spatial_rainbow",
+        description: "Rainbows up the tree, around it or out from the trunk.",
+        params: spatial_rainbow::PARAMS,
+        constructor: construct::<spatial_rainbow::SpatialRainbow>,
+    },
+    PatternInfo {
+        name: "balls",
+        description: "Bounces coloured balls around the tree's bounding box.",
+        params: balls::PARAMS,
+        constructor: construct::<balls::BallPattern>,
+    },
+    PatternInfo {
+        name: "sweep",
+        description: "Sweeps a glowing plane through the tree, in any direction.",
+        params: sweep::PARAMS,
+        constructor: construct::<sweep::Sweep>,
+    },
+];
+
+/// Looks up a registered pattern by name.
+pub fn find(name: &str) -> Option<&'static PatternInfo> {
+    PATTERNS.iter().find(|info| info.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::vec3;
+
+    #[test]
+    fn same_frame_at_any_rate() {
+        let tree = [
+            vec3(-0.5, 0.2, 0.),
+            vec3(0.3, -0.4, 0.8),
+            vec3(0., 0.5, 1.6),
+        ];
+        for name in ["rainbow", "spatial_rainbow", "sweep"] {
+            let info = find(name).unwrap();
+            let args = PatternArgs::parse(info.params, None).unwrap();
+            let mut slow = info.create(&tree, &args, 0);
+            let mut fast = info.create(&tree, &args, 0);
 
-    fn next_frame(&mut self) -> Option<Vec<Color>>;
+            // 1.5 seconds in either way
+            let slow = slow.next_frame(&FrameContext::at_rate(45, 30)).unwrap();
+            let fast = fast.next_frame(&FrameContext::at_rate(90, 60)).unwrap();
+            for (a, b) in slow.iter().zip(&fast) {
+                assert!(
+                    (a.r - b.r).abs() < 1e-4
+                        && (a.g - b.g).abs() < 1e-4
+                        && (a.b - b.b).abs() < 1e-4,
+                    "{}: {:?} {:?}",
+                    name,
+                    a,
+                    b
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,334 @@
+//! Typed pattern parameters.
+//!
+//! Each pattern declares a schema of [`ParamSpec`]s, and `--pattern-args` is
+//! validated against it before the pattern is built. Patterns then read typed
+//! values out of [`PatternArgs`] without any parsing of their own.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A parameter value. The variant of a spec's default sets the parameter type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamValue {
+    Int(i64),
+    Float(f64),
+}
+
+impl ParamValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ParamValue::Int(_) => "int",
+            ParamValue::Float(_) => "float",
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match *self {
+            ParamValue::Int(v) => v as f64,
+            ParamValue::Float(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamValue::Int(v) => write!(f, "{}", v),
+            ParamValue::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Declaration of a single pattern parameter.
+#[derive(Debug)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: ParamValue,
+    /// Smallest accepted value, inclusive.
+    pub min: ParamValue,
+    /// Largest accepted value, inclusive.
+    pub max: ParamValue,
+    /// Names an int parameter also accepts, standing for `min` upwards in order.
+    pub choices: &'static [&'static str],
+}
+
+impl ParamSpec {
+    /// Parses and range-checks a value for this parameter.
+    fn parse(&self, value: &str) -> Result<ParamValue, ArgsError> {
+        let parsed = match self.default {
+            ParamValue::Int(_) => value
+                .parse()
+                .ok()
+                .or_else(|| self.choice(value))
+                .map(ParamValue::Int),
+            ParamValue::Float(_) => value
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .map(ParamValue::Float),
+        };
+
+        let parsed = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                return Err(ArgsError::InvalidValue {
+                    key: String::from(self.name),
+                    value: String::from(value),
+                    expected: self.default.type_name(),
+                })
+            }
+        };
+
+        let v = parsed.as_f64();
+        if v < self.min.as_f64() || v > self.max.as_f64() {
+            return Err(ArgsError::OutOfRange {
+                key: String::from(self.name),
+                value: parsed,
+                min: self.min,
+                max: self.max,
+            });
+        }
+
+        Ok(parsed)
+    }
+
+    /// The value `name` stands for, if it's one of the choices.
+    fn choice(&self, name: &str) -> Option<i64> {
+        let i = self.choices.iter().position(|choice| *choice == name)?;
+        Some(self.min.as_f64() as i64 + i as i64)
+    }
+}
+
+/// Problems with user-supplied pattern arguments.
+#[derive(Debug)]
+pub enum ArgsError {
+    /// An argument wasn't in `key=value` form.
+    Malformed(String),
+    /// The pattern has no parameter by this name.
+    UnknownKey {
+        key: String,
+        known: Vec<&'static str>,
+    },
+    /// The value couldn't be parsed as the parameter's type.
+    InvalidValue {
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+    /// The value is outside the parameter's range.
+    OutOfRange {
+        key: String,
+        value: ParamValue,
+        min: ParamValue,
+        max: ParamValue,
+    },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Malformed(arg) => {
+                write!(f, "pattern argument \"{}\" is not key=value", arg)
+            }
+            ArgsError::UnknownKey { key, known } => {
+                if known.is_empty() {
+                    write!(
+                        f,
+                        "unknown pattern argument \"{}\", pattern takes no arguments",
+                        key
+                    )
+                } else {
+                    write!(
+                        f,
+                        "unknown pattern argument \"{}\", expected one of: {}",
+                        key,
+                        known.join(", ")
+                    )
+                }
+            }
+            ArgsError::InvalidValue {
+                key,
+                value,
+                expected,
+            } => write!(
+                f,
+                "pattern argument {}=\"{}\" is not a valid {}",
+                key, value, expected
+            ),
+            ArgsError::OutOfRange {
+                key,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "pattern argument {}={} is out of range ({} to {})",
+                key, value, min, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+/// Validated arguments for a pattern, with defaults filled in.
+///
+/// Displays as a complete `--pattern-args` string, in schema order.
+#[derive(Clone, Debug)]
+pub struct PatternArgs {
+    specs: &'static [ParamSpec],
+    values: HashMap<&'static str, ParamValue>,
+}
+
+impl PatternArgs {
+    /// Validates semicolon-separated `key=value` pairs against a schema.
+    pub fn parse(specs: &'static [ParamSpec], args: Option<&str>) -> Result<Self, ArgsError> {
+        let mut values: HashMap<&'static str, ParamValue> =
+            specs.iter().map(|spec| (spec.name, spec.default)).collect();
+
+        // Trailing or doubled-up semicolons are harmless
+        let args = args.unwrap_or("");
+        for arg in args.split(';').map(str::trim).filter(|arg| !arg.is_empty()) {
+            let mut kv = arg.splitn(2, '=');
+            let key = kv.next().unwrap_or("").trim();
+            let value = match kv.next() {
+                Some(value) if !key.is_empty() => value.trim(),
+                _ => return Err(ArgsError::Malformed(String::from(arg))),
+            };
+
+            let spec = match specs.iter().find(|spec| spec.name == key) {
+                Some(spec) => spec,
+                None => {
+                    return Err(ArgsError::UnknownKey {
+                        key: String::from(key),
+                        known: specs.iter().map(|spec| spec.name).collect(),
+                    })
+                }
+            };
+
+            values.insert(spec.name, spec.parse(value)?);
+        }
+
+        Ok(PatternArgs { specs, values })
+    }
+
+    /// Gets an integer parameter.
+    ///
+    /// Panics if the pattern didn't declare `name` as an int, which is a bug
+    /// in the pattern rather than bad user input.
+    pub fn int(&self, name: &str) -> i64 {
+        match self.values.get(name) {
+            Some(ParamValue::Int(v)) => *v,
+            _ => panic!("pattern has no int parameter \"{}\"", name),
+        }
+    }
+
+    /// Gets a float parameter.
+    ///
+    /// Panics if the pattern didn't declare `name` as a float.
+    pub fn float(&self, name: &str) -> f32 {
+        match self.values.get(name) {
+            Some(ParamValue::Float(v)) => *v as f32,
+            _ => panic!("pattern has no float parameter \"{}\"", name),
+        }
+    }
+}
+
+impl fmt::Display for PatternArgs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, spec) in self.specs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ";")?;
+            }
+            write!(f, "{}={}", spec.name, self.values[spec.name])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPECS: &[ParamSpec] = &[
+        ParamSpec {
+            name: "num",
+            description: "A count.",
+            default: ParamValue::Int(6),
+            min: ParamValue::Int(0),
+            max: ParamValue::Int(10),
+            choices: &[],
+        },
+        ParamSpec {
+            name: "vel",
+            description: "A speed.",
+            default: ParamValue::Float(0.5),
+            min: ParamValue::Float(0.),
+            max: ParamValue::Float(2.),
+            choices: &[],
+        },
+    ];
+
+    #[test]
+    fn defaults_and_overrides() {
+        let args = PatternArgs::parse(SPECS, None).unwrap();
+        assert_eq!(args.int("num"), 6);
+        assert_eq!(args.float("vel"), 0.5);
+
+        let args = PatternArgs::parse(SPECS, Some(" vel = 1.5;num=3;")).unwrap();
+        assert_eq!(args.int("num"), 3);
+        assert_eq!(args.float("vel"), 1.5);
+        assert_eq!(args.to_string(), "num=3;vel=1.5");
+    }
+
+    #[test]
+    fn choices() {
+        const MODE: &[ParamSpec] = &[ParamSpec {
+            name: "mode",
+            description: "A mode.",
+            default: ParamValue::Int(1),
+            min: ParamValue::Int(1),
+            max: ParamValue::Int(3),
+            choices: &["up", "down", "round"],
+        }];
+
+        let args = PatternArgs::parse(MODE, Some("mode=round")).unwrap();
+        assert_eq!(args.int("mode"), 3);
+        assert_eq!(args.to_string(), "mode=3");
+        let args = PatternArgs::parse(MODE, Some("mode=2")).unwrap();
+        assert_eq!(args.int("mode"), 2);
+        assert!(matches!(
+            PatternArgs::parse(MODE, Some("mode=sideways")),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_args() {
+        assert!(matches!(
+            PatternArgs::parse(SPECS, Some("num")),
+            Err(ArgsError::Malformed(_))
+        ));
+        assert!(matches!(
+            PatternArgs::parse(SPECS, Some("=4")),
+            Err(ArgsError::Malformed(_))
+        ));
+        assert!(matches!(
+            PatternArgs::parse(SPECS, Some("speed=1")),
+            Err(ArgsError::UnknownKey { .. })
+        ));
+        assert!(matches!(
+            PatternArgs::parse(SPECS, Some("num=1.5")),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            PatternArgs::parse(SPECS, Some("vel=NaN")),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            PatternArgs::parse(SPECS, Some("num=11")),
+            Err(ArgsError::OutOfRange { .. })
+        ));
+    }
+}
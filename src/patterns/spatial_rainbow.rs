@@ -0,0 +1,206 @@
+use super::{FrameContext, FromTree, ParamSpec, ParamValue, Pattern, PatternArgs, PatternRng};
+use crate::colors::{Color, Gradient, PALETTES};
+use crate::tree::{self, Pixel};
+
+use std::f32::consts::TAU;
+
+pub const PARAMS: &[ParamSpec] = &[
+    ParamSpec {
+        name: "mapping",
+        description: "What the rainbow follows: 0 height, 1 angle around the trunk, 2 distance from the trunk.",
+        default: ParamValue::Int(0),
+        min: ParamValue::Int(0),
+        max: ParamValue::Int(2),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "bands",
+        description: "Number of rainbows from bottom to top, once around, or trunk to tips.",
+        default: ParamValue::Int(1),
+        min: ParamValue::Int(1),
+        max: ParamValue::Int(100),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "scroll",
+        description: "Rainbows per second the colours move by, negative goes the other way.",
+        default: ParamValue::Float(0.25),
+        min: ParamValue::Float(-20.),
+        max: ParamValue::Float(20.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "twist",
+        description: "Rainbows added going around the trunk (or up the tree, when following angle), making spirals.",
+        default: ParamValue::Float(0.),
+        min: ParamValue::Float(-20.),
+        max: ParamValue::Float(20.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "palette",
+        description: "Every hue, or the colours of a palette, by name or number.",
+        default: ParamValue::Int(0),
+        min: ParamValue::Int(0),
+        max: ParamValue::Int(PALETTES.len() as i64),
+        choices: &PALETTE_CHOICES,
+    },
+    ParamSpec {
+        name: "saturation",
+        description: "Colour saturation, 0 for white. Every hue only.",
+        default: ParamValue::Float(1.),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "lightness",
+        description: "0.5 for full colours, higher washes them out towards white. Every hue only.",
+        default: ParamValue::Float(0.5),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+];
+
+/// `palette` by name, every hue and then each of [`PALETTES`].
+const PALETTE_CHOICES: [&str; PALETTES.len() + 1] = {
+    let mut choices = ["hues"; PALETTES.len() + 1];
+    let mut i = 0;
+    while i < PALETTES.len() {
+        choices[i + 1] = PALETTES[i].0;
+        i += 1;
+    }
+    choices
+};
+
+/// Rainbows laid out over the tree's shape rather than along the string.
+pub struct SpatialRainbow {
+    /// Where each pixel sits in the rainbow at the start, in turns of hue.
+    hues: Vec<f32>,
+    /// Turns of hue per second.
+    scroll: f32,
+    /// Colours to use in place of the hue wheel.
+    palette: Option<Gradient>,
+    saturation: f32,
+    lightness: f32,
+}
+
+impl FromTree for SpatialRainbow {
+    fn from_tree(tree: &[Pixel], args: &PatternArgs, _rng: PatternRng) -> Self {
+        let (min, max) = tree::bounds(tree);
+        let height = (max.z - min.z).max(f32::EPSILON);
+        let radius = tree
+            .iter()
+            .map(|pixel| pixel.truncate().length())
+            .fold(f32::EPSILON, f32::max);
+
+        let mapping = args.int("mapping");
+        let bands = args.int("bands") as f32;
+        let twist = args.float("twist");
+
+        // Each pixel's place along the mapping and along the twist, both 0.0-1.0
+        let hues = tree
+            .iter()
+            .map(|pixel| {
+                let up = (pixel.z - min.z) / height;
+                let around = pixel.y.atan2(pixel.x).rem_euclid(TAU) / TAU;
+                let out = pixel.truncate().length() / radius;
+                let (along, across) = match mapping {
+                    0 => (up, around),
+                    1 => (around, up),
+                    _ => (out, around),
+                };
+                along * bands + across * twist
+            })
+            .collect();
+
+        SpatialRainbow {
+            hues,
+            scroll: args.float("scroll"),
+            palette: match args.int("palette") {
+                0 => None,
+                n => Some(Gradient::new(PALETTES[n as usize - 1].1)),
+            },
+            saturation: args.float("saturation"),
+            lightness: args.float("lightness"),
+        }
+    }
+}
+
+impl Pattern for SpatialRainbow {
+    fn next_frame(&mut self, ctx: &FrameContext) -> Option<Vec<Color>> {
+        // Subtracted so positive scroll moves colours up, round or outwards
+        let offset = ctx.time * self.scroll;
+        let frame = self
+            .hues
+            .iter()
+            .map(|hue| {
+                let turns = (hue - offset).rem_euclid(1.);
+                match &self.palette {
+                    Some(palette) => palette.sample_looped(turns),
+                    None => Color::from_hsl(turns * 360., self.saturation, self.lightness),
+                }
+            })
+            .collect();
+
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors::CHRISTMAS;
+    use glam::vec3;
+    use rand::SeedableRng;
+
+    fn rainbow(args: &str) -> SpatialRainbow {
+        // Bottom, middle and top, a quarter turn further round each time
+        let tree = [vec3(1., 0., 0.), vec3(0., 0.5, 1.), vec3(-0.5, 0., 2.)];
+        let args = PatternArgs::parse(PARAMS, Some(args)).unwrap();
+        SpatialRainbow::from_tree(&tree, &args, PatternRng::seed_from_u64(0))
+    }
+
+    fn close(a: Color, b: Color) -> bool {
+        (a.r - b.r).abs() < 1e-3 && (a.g - b.g).abs() < 1e-3 && (a.b - b.b).abs() < 1e-3
+    }
+
+    #[test]
+    fn mappings() {
+        let hues = |args| rainbow(args).hues;
+        assert_eq!(hues("mapping=0"), vec![0., 0.5, 1.]);
+        assert_eq!(hues("mapping=1"), vec![0., 0.25, 0.5]);
+        assert_eq!(hues("mapping=2"), vec![1., 0.5, 0.5]);
+
+        // Bands repeat along the mapping, twist adds on going around
+        assert_eq!(hues("mapping=0;bands=2;twist=1"), vec![0., 1.25, 2.5]);
+        assert_eq!(hues("mapping=1;twist=-2"), vec![0., -0.75, -1.5]);
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut pattern = rainbow("scroll=0.25");
+        // A quarter of the way on, and moving up the tree
+        let frame = pattern.next_frame(&FrameContext::at_rate(30, 30)).unwrap();
+        assert!(close(frame[0], Color::from_hsl(270., 1., 0.5)));
+        assert!(close(frame[1], Color::from_hsl(90., 1., 0.5)));
+        assert!(close(frame[2], Color::from_hsl(270., 1., 0.5)));
+
+        let mut pattern = rainbow("scroll=0;saturation=0;lightness=0.8");
+        let frame = pattern.next_frame(&FrameContext::at_rate(0, 30)).unwrap();
+        assert!(close(frame[1], Color::new(0.8, 0.8, 0.8, 1.)));
+    }
+
+    #[test]
+    fn palettes() {
+        let mut pattern = rainbow("scroll=0;palette=christmas");
+        let frame = pattern.next_frame(&FrameContext::at_rate(0, 30)).unwrap();
+        assert!(close(frame[0], CHRISTMAS[0]));
+        assert!(close(frame[1], Gradient::new(CHRISTMAS).sample_looped(0.5)));
+
+        // By number works too
+        assert!(rainbow("palette=1").palette.is_some());
+        assert!(rainbow("palette=hues").palette.is_none());
+    }
+}
@@ -0,0 +1,308 @@
+use super::{FrameContext, FromTree, ParamSpec, ParamValue, Pattern, PatternArgs, PatternRng};
+use crate::colors::Color;
+use crate::tree::Pixel;
+
+use glam::{vec3, Quat, Vec3};
+use std::f32::consts::TAU;
+
+pub const PARAMS: &[ParamSpec] = &[
+    ParamSpec {
+        name: "nx",
+        description: "X part of the direction the plane moves in.",
+        default: ParamValue::Float(0.),
+        min: ParamValue::Float(-1.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "ny",
+        description: "Y part of the direction the plane moves in.",
+        default: ParamValue::Float(0.),
+        min: ParamValue::Float(-1.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "nz",
+        description: "Z part of the direction the plane moves in, upwards by default.",
+        default: ParamValue::Float(1.),
+        min: ParamValue::Float(-1.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "period",
+        description: "Seconds to sweep once through the whole tree.",
+        default: ParamValue::Float(3.),
+        min: ParamValue::Float(0.1),
+        max: ParamValue::Float(600.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "bounce",
+        description: "1 to sweep back and forth, 0 to start over from the far side.",
+        default: ParamValue::Int(0),
+        min: ParamValue::Int(0),
+        max: ParamValue::Int(1),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "width",
+        description: "Thickness of the fully lit slab, 0 for a bare plane.",
+        default: ParamValue::Float(0.1),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(10.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "softness",
+        description: "Distance either side of the slab over which it fades out.",
+        default: ParamValue::Float(0.15),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(10.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "spin",
+        description: "Turns per second of the direction around the trunk.",
+        default: ParamValue::Float(0.),
+        min: ParamValue::Float(-10.),
+        max: ParamValue::Float(10.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "tumble",
+        description: "Turns per second of the direction tipping over, through upright.",
+        default: ParamValue::Float(0.),
+        min: ParamValue::Float(-10.),
+        max: ParamValue::Float(10.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "red",
+        description: "Red of the slab.",
+        default: ParamValue::Float(1.),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "green",
+        description: "Green of the slab.",
+        default: ParamValue::Float(0.8),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+    ParamSpec {
+        name: "blue",
+        description: "Blue of the slab.",
+        default: ParamValue::Float(0.4),
+        min: ParamValue::Float(0.),
+        max: ParamValue::Float(1.),
+        choices: &[],
+    },
+];
+
+/// Moves a lit slab through the tree, lighting whatever it passes.
+///
+/// The slab is every point within `width / 2` of a plane, fading out over
+/// `softness` beyond that. The plane moves along its normal from one side of
+/// the tree to the other, and the normal can turn while it does.
+pub struct Sweep {
+    tree: Vec<Pixel>,
+    normal: Vec3,
+    period: f32,
+    bounce: bool,
+    half_width: f32,
+    softness: f32,
+    /// Radians per second.
+    spin: f32,
+    tumble: f32,
+    color: Color,
+}
+
+impl Sweep {
+    /// Direction the plane faces `time` seconds in.
+    fn normal_at(&self, time: f32) -> Vec3 {
+        // Tip over around the horizontal axis square to the normal, then spin
+        // around the trunk
+        let horizontal = self.normal.cross(Vec3::Z);
+        let tip_axis = if horizontal.length_squared() > 1e-6 {
+            horizontal.normalize()
+        } else {
+            Vec3::X
+        };
+        let tipped = Quat::from_axis_angle(tip_axis, self.tumble * time).mul_vec3(self.normal);
+        Quat::from_rotation_z(self.spin * time).mul_vec3(tipped)
+    }
+
+    /// How far through a sweep `time` seconds is, 0.0-1.0.
+    fn progress(&self, time: f32) -> f32 {
+        let passes = time / self.period;
+        if self.bounce {
+            // There on even passes, back on odd ones
+            let phase = passes % 2.;
+            if phase <= 1. {
+                phase
+            } else {
+                2. - phase
+            }
+        } else {
+            passes.fract()
+        }
+    }
+
+    /// Brightness of a point `distance` from the plane, 0.0-1.0.
+    fn brightness(&self, distance: f32) -> f32 {
+        let outside = distance.abs() - self.half_width;
+        if outside <= 0. {
+            1.
+        } else if outside >= self.softness {
+            0.
+        } else {
+            // Smoothstep, so the edge doesn't have a visible kink
+            let t = 1. - outside / self.softness;
+            t * t * (3. - 2. * t)
+        }
+    }
+}
+
+impl FromTree for Sweep {
+    fn from_tree(tree: &[Pixel], args: &PatternArgs, _rng: PatternRng) -> Self {
+        let normal = vec3(args.float("nx"), args.float("ny"), args.float("nz"));
+        // All zeroes doesn't go anywhere, fall back to upwards
+        let normal = if normal.length_squared() > 1e-6 {
+            normal.normalize()
+        } else {
+            Vec3::Z
+        };
+
+        Sweep {
+            tree: tree.to_vec(),
+            normal,
+            period: args.float("period"),
+            bounce: args.int("bounce") != 0,
+            half_width: args.float("width") / 2.,
+            softness: args.float("softness"),
+            spin: args.float("spin") * TAU,
+            tumble: args.float("tumble") * TAU,
+            color: Color::new(
+                args.float("red"),
+                args.float("green"),
+                args.float("blue"),
+                1.,
+            ),
+        }
+    }
+}
+
+impl Pattern for Sweep {
+    fn next_frame(&mut self, ctx: &FrameContext) -> Option<Vec<Color>> {
+        let normal = self.normal_at(ctx.time);
+
+        // Distance of every pixel along the normal. The sweep starts and ends
+        // just clear of the tree, so the slab enters and leaves it entirely.
+        let heights: Vec<f32> = self.tree.iter().map(|pixel| pixel.dot(normal)).collect();
+        let margin = self.half_width + self.softness;
+        let start = heights.iter().cloned().fold(f32::INFINITY, f32::min) - margin;
+        let end = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max) + margin;
+        let plane = start + (end - start) * self.progress(ctx.time);
+
+        let frame = heights
+            .iter()
+            .map(|height| self.color.scale(self.brightness(height - plane)))
+            .collect();
+
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn sweep(args: &str) -> Sweep {
+        let tree = [vec3(0., 0., 0.), vec3(0., 0., 0.5), vec3(0., 0., 1.)];
+        let args = PatternArgs::parse(PARAMS, Some(args)).unwrap();
+        Sweep::from_tree(&tree, &args, PatternRng::seed_from_u64(0))
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    fn close_vec(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn progress() {
+        let wrap = sweep("period=2");
+        assert!(close(wrap.progress(0.), 0.));
+        assert!(close(wrap.progress(1.), 0.5));
+        assert!(close(wrap.progress(2.5), 0.25));
+
+        let bounce = sweep("period=2;bounce=1");
+        assert!(close(bounce.progress(1.), 0.5));
+        assert!(close(bounce.progress(2.), 1.));
+        // On the way back
+        assert!(close(bounce.progress(3.), 0.5));
+        assert!(close(bounce.progress(3.5), 0.25));
+        assert!(close(bounce.progress(4.5), 0.25));
+    }
+
+    #[test]
+    fn brightness() {
+        let slab = sweep("width=0.2;softness=0.1");
+        // Fully lit either side of the plane
+        assert_eq!(slab.brightness(0.), 1.);
+        assert_eq!(slab.brightness(-0.1), 1.);
+        // Smoothstep over the softness, half way at its middle
+        assert!(close(slab.brightness(0.15), 0.5));
+        assert!(close(slab.brightness(-0.125), 0.84375));
+        assert!(close(slab.brightness(0.175), 0.15625));
+        // Nothing past it
+        assert_eq!(slab.brightness(0.2), 0.);
+        assert_eq!(slab.brightness(-3.), 0.);
+
+        // No softness cuts off right at the edge
+        let hard = sweep("width=0.2;softness=0");
+        assert_eq!(hard.brightness(0.1), 1.);
+        assert_eq!(hard.brightness(0.1001), 0.);
+    }
+
+    #[test]
+    fn normal_at() {
+        // All zeroes falls back to upwards, and the tip axis to X
+        let upright = sweep("nz=0;tumble=0.25");
+        assert!(close_vec(upright.normal_at(0.), Vec3::Z));
+        assert!(close_vec(upright.normal_at(1.), -Vec3::Y));
+
+        let spin = sweep("nx=1;nz=0;spin=0.25");
+        assert!(close_vec(spin.normal_at(0.), Vec3::X));
+        assert!(close_vec(spin.normal_at(1.), Vec3::Y));
+        assert!(close_vec(spin.normal_at(2.), -Vec3::X));
+
+        // Sideways tumbles up through upright
+        let tumble = sweep("nx=1;nz=0;tumble=0.25");
+        assert!(close_vec(tumble.normal_at(1.), Vec3::Z));
+        assert!(close_vec(tumble.normal_at(2.), -Vec3::X));
+    }
+
+    #[test]
+    fn frame() {
+        let mut pattern = sweep("period=1;width=0.2;softness=0.1;red=1;green=0;blue=0");
+        let red = Color::new(1., 0., 0., 1.);
+        let black = Color::new(0., 0., 0., 1.);
+
+        // Starts just below the tree
+        let frame = pattern.next_frame(&FrameContext::at_rate(0, 30)).unwrap();
+        assert_eq!(frame, vec![black, black, black]);
+
+        // Half way through only lights the middle
+        let frame = pattern.next_frame(&FrameContext::at_rate(15, 30)).unwrap();
+        assert_eq!(frame, vec![black, red, black]);
+    }
+}
@@ -0,0 +1,296 @@
+//! Playing back pre-rendered animations as patterns.
+//!
+//! Reads frame files in the same CSV form `export` writes:
+//! FRAME_ID, R_0, G_0, B_0, R_1, G_1, B_1, ...
+//!
+//! Channels are normally 0-255 integers. Files written with `export --float`
+//! (every value within 0.0-1.0, with at least one fraction) are read as-is.
+//!
+//! FSEQ sequences are read too, assuming one RGB triple per pixel.
+
+use std::fmt;
+use std::io::Error;
+use std::rc::Rc;
+
+use crate::colors::Color;
+use crate::fseq::{self, FseqError};
+use crate::patterns::{FrameContext, Pattern};
+
+/// Everything that can go wrong reading a frame file.
+///
+/// Line and column numbers are 1-based.
+#[derive(Debug)]
+pub enum FramesError {
+    Io(Error),
+    Fseq(FseqError),
+    /// The first row isn't a `FRAME_ID,R_0,G_0,B_0,...` header.
+    BadHeader,
+    /// The file describes a different number of pixels than the tree has.
+    PixelMismatch {
+        file: usize,
+        tree: usize,
+    },
+    /// A row has a different number of columns than the header.
+    ColumnCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A value isn't a number.
+    InvalidValue {
+        line: usize,
+        column: usize,
+        value: String,
+    },
+    /// The file has a header but no frames.
+    Empty,
+}
+
+impl fmt::Display for FramesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FramesError::Io(err) => write!(f, "{}", err),
+            FramesError::Fseq(err) => write!(f, "{}", err),
+            FramesError::BadHeader => {
+                write!(f, "line 1: expected a FRAME_ID,R_0,G_0,B_0,... header")
+            }
+            FramesError::PixelMismatch { file, tree } => {
+                write!(f, "file has {} pixels but the tree has {}", file, tree)
+            }
+            FramesError::ColumnCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} columns, found {}",
+                line, expected, found
+            ),
+            FramesError::InvalidValue {
+                line,
+                column,
+                value,
+            } => write!(
+                f,
+                "line {}, column {}: invalid value \"{}\"",
+                line, column, value
+            ),
+            FramesError::Empty => write!(f, "file contains no frames"),
+        }
+    }
+}
+
+impl std::error::Error for FramesError {}
+
+impl From<Error> for FramesError {
+    fn from(err: Error) -> Self {
+        FramesError::Io(err)
+    }
+}
+
+impl From<FseqError> for FramesError {
+    fn from(err: FseqError) -> Self {
+        FramesError::Fseq(err)
+    }
+}
+
+/// Reads frames from an FSEQ file, along with its frame step time in ms.
+pub fn import_fseq(path: &str, num_pixels: usize) -> Result<(Vec<Vec<Color>>, u8), FramesError> {
+    let file = std::fs::File::open(path)?;
+    let sequence = fseq::read(std::io::BufReader::new(file))?;
+    let frames = fseq_frames(&sequence, num_pixels)?;
+    Ok((frames, sequence.step_ms))
+}
+
+fn fseq_frames(
+    sequence: &fseq::Sequence,
+    num_pixels: usize,
+) -> Result<Vec<Vec<Color>>, FramesError> {
+    if sequence.channels != num_pixels * 3 {
+        // Not all channels are necessarily pixels, but it's the best guess
+        return Err(FramesError::PixelMismatch {
+            file: sequence.channels / 3,
+            tree: num_pixels,
+        });
+    }
+    if sequence.frames.is_empty() {
+        return Err(FramesError::Empty);
+    }
+
+    Ok(sequence
+        .frames
+        .iter()
+        .map(|frame| {
+            frame
+                .chunks(3)
+                .map(|rgb| Color::from_rgba(rgb[0], rgb[1], rgb[2], 255))
+                .collect()
+        })
+        .collect())
+}
+
+pub fn import_csv(path: &str, num_pixels: usize) -> Result<Vec<Vec<Color>>, FramesError> {
+    let csv = std::fs::read_to_string(path)?;
+    parse_csv(&csv, num_pixels)
+}
+
+/// Parses a frame CSV, checking it lines up with a tree of `num_pixels`.
+pub fn parse_csv(csv: &str, num_pixels: usize) -> Result<Vec<Vec<Color>>, FramesError> {
+    let csv = csv.strip_prefix('\u{feff}').unwrap_or(csv);
+    let mut lines = csv
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    let header: Vec<&str> = match lines.next() {
+        Some((_, header)) => header.split(',').map(str::trim).collect(),
+        None => return Err(FramesError::BadHeader),
+    };
+    if !header[0].eq_ignore_ascii_case("FRAME_ID") || !(header.len() - 1).is_multiple_of(3) {
+        return Err(FramesError::BadHeader);
+    }
+    let columns = header.len();
+    let file_pixels = (columns - 1) / 3;
+    if file_pixels != num_pixels {
+        return Err(FramesError::PixelMismatch {
+            file: file_pixels,
+            tree: num_pixels,
+        });
+    }
+
+    // Parse everything first, the channel scale depends on the whole file
+    let mut rows = Vec::new();
+    let mut max: f32 = 0.;
+    // Float exports always carry a decimal point, even on 0.0 and 1.0
+    let mut decimal = false;
+    for (line_num, line) in lines {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != columns {
+            return Err(FramesError::ColumnCount {
+                line: line_num,
+                expected: columns,
+                found: fields.len(),
+            });
+        }
+
+        // Frame ID is skipped, frames play in file order
+        let mut values = Vec::with_capacity(columns - 1);
+        for (i, field) in fields.iter().enumerate().skip(1) {
+            let value = match field.parse::<f32>() {
+                Ok(v) if v.is_finite() => v,
+                _ => {
+                    return Err(FramesError::InvalidValue {
+                        line: line_num,
+                        column: i + 1,
+                        value: String::from(*field),
+                    })
+                }
+            };
+            max = max.max(value);
+            decimal |= field.contains('.');
+            values.push(value);
+        }
+        rows.push(values);
+    }
+
+    if rows.is_empty() {
+        return Err(FramesError::Empty);
+    }
+
+    let scale = if decimal && max <= 1. { 1. } else { 255. };
+    Ok(rows
+        .iter()
+        .map(|row| {
+            row.chunks(3)
+                .map(|rgb| Color::new(rgb[0] / scale, rgb[1] / scale, rgb[2] / scale, 1.))
+                .collect()
+        })
+        .collect())
+}
+
+/// Plays a list of frames once through, one per call regardless of timing.
+pub struct FramePlayback {
+    /// Shared so restarting playback doesn't need a fresh copy.
+    frames: Rc<Vec<Vec<Color>>>,
+    index: usize,
+}
+
+impl FramePlayback {
+    pub fn new(frames: Rc<Vec<Vec<Color>>>) -> Self {
+        FramePlayback { frames, index: 0 }
+    }
+}
+
+impl Pattern for FramePlayback {
+    fn next_frame(&mut self, _ctx: &FrameContext) -> Option<Vec<Color>> {
+        let frame = self.frames.get(self.index)?.clone();
+        self.index += 1;
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_frames() {
+        let csv = "FRAME_ID,R_0,G_0,B_0,R_1,G_1,B_1\r\n\
+                   0,255,0,51,0,0,0\r\n\
+                   1, 0 ,255,0,1,1,1\r\n";
+        let frames = parse_csv(csv, 2).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0][0], Color::new(1., 0., 0.2, 1.));
+        assert_eq!(frames[1][1], Color::from_rgba(1, 1, 1, 255));
+
+        let mut playback = FramePlayback::new(Rc::new(frames));
+        for i in 0..2 {
+            assert!(playback.next_frame(&FrameContext::at_rate(i, 30)).is_some());
+        }
+        assert!(playback.next_frame(&FrameContext::at_rate(2, 30)).is_none());
+    }
+
+    #[test]
+    fn float_frames() {
+        let frames = parse_csv("FRAME_ID,R_0,G_0,B_0\n0,0.5,1,0\n", 1).unwrap();
+        assert_eq!(frames[0][0], Color::new(0.5, 1., 0., 1.));
+
+        // Nothing but full and off is still floats, going by the decimal point
+        let frames = parse_csv("FRAME_ID,R_0,G_0,B_0\n0,0.0,1.0,0.0\n", 1).unwrap();
+        assert_eq!(frames[0][0], Color::new(0., 1., 0., 1.));
+    }
+
+    #[test]
+    fn validation() {
+        assert!(matches!(parse_csv("", 1), Err(FramesError::BadHeader)));
+        assert!(matches!(
+            parse_csv("R_0,G_0,B_0\n", 1),
+            Err(FramesError::BadHeader)
+        ));
+        assert!(matches!(
+            parse_csv("FRAME_ID,R_0,G_0,B_0\n", 1),
+            Err(FramesError::Empty)
+        ));
+        assert!(matches!(
+            parse_csv("FRAME_ID,R_0,G_0,B_0\n0,1,2,3\n", 2),
+            Err(FramesError::PixelMismatch { file: 1, tree: 2 })
+        ));
+        assert!(matches!(
+            parse_csv("FRAME_ID,R_0,G_0,B_0\n0,1,2,3\n1,1,2\n", 1),
+            Err(FramesError::ColumnCount {
+                line: 3,
+                expected: 4,
+                found: 3
+            })
+        ));
+        assert!(matches!(
+            parse_csv("FRAME_ID,R_0,G_0,B_0\n0,1,x,3\n", 1),
+            Err(FramesError::InvalidValue {
+                line: 2,
+                column: 3,
+                ..
+            })
+        ));
+    }
+}
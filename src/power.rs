@@ -0,0 +1,223 @@
+//! Estimating how much current a frame draws, and keeping it under what the
+//! supply can give.
+//!
+//! Each channel is taken to draw in proportion to its value, so a full white
+//! pixel draws three times `milliamps_per_channel`, plus a little for the
+//! controller in every LED whatever it shows. Close enough for WS2811/WS2812
+//! style strings, which is what supplies get sized against anyway.
+//!
+//! Limiting scales the whole frame down evenly, so colours and the balance
+//! between pixels are kept and the tree just gets dimmer.
+
+use std::fmt;
+use std::io;
+
+use crate::colors::Color;
+use crate::output::Output;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerModel {
+    /// Current a single channel draws at full, in mA.
+    pub milliamps_per_channel: f32,
+    /// Current every LED draws even when off, in mA.
+    pub idle_milliamps: f32,
+    /// Most current the supply can give, in mA. Frames are only scaled down
+    /// when there is one.
+    pub limit: Option<f32>,
+}
+
+impl Default for PowerModel {
+    /// 20 mA a channel and 1 mA idle, as on WS2812B datasheets, with no limit.
+    fn default() -> Self {
+        PowerModel {
+            milliamps_per_channel: 20.,
+            idle_milliamps: 1.,
+            limit: None,
+        }
+    }
+}
+
+impl PowerModel {
+    /// Current drawn showing `frame`, in mA.
+    pub fn estimate(&self, frame: &[Color]) -> f32 {
+        let channels: f32 = frame
+            .iter()
+            .map(|c| c.r.clamp(0., 1.) + c.g.clamp(0., 1.) + c.b.clamp(0., 1.))
+            .sum();
+        channels * self.milliamps_per_channel + frame.len() as f32 * self.idle_milliamps
+    }
+
+    /// Scales `frame` down to fit under the limit if it needs to.
+    pub fn limit(&self, frame: &mut [Color]) -> Draw {
+        let current = self.estimate(frame);
+        let limit = match self.limit {
+            Some(limit) if current > limit => limit,
+            _ => {
+                return Draw {
+                    requested: current,
+                    drawn: current,
+                }
+            }
+        };
+
+        // Idle current is there whatever the colours, only the rest scales
+        let idle = frame.len() as f32 * self.idle_milliamps;
+        let scale = ((limit - idle) / (current - idle)).max(0.);
+        for color in frame.iter_mut() {
+            *color = color.clamped().scale(scale);
+        }
+        Draw {
+            requested: current,
+            drawn: idle + (current - idle) * scale,
+        }
+    }
+}
+
+/// Current a frame draws, in mA.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Draw {
+    /// As the pattern made it.
+    pub requested: f32,
+    /// After limiting.
+    pub drawn: f32,
+}
+
+impl Draw {
+    pub fn is_limited(&self) -> bool {
+        self.drawn < self.requested
+    }
+}
+
+/// Peak and average current over a run of frames.
+#[derive(Clone, Debug, Default)]
+pub struct PowerStats {
+    frames: usize,
+    total: f64,
+    /// Highest current any frame asked for, before limiting.
+    peak: f32,
+    peak_frame: usize,
+    /// Frames that were scaled down.
+    limited: usize,
+    /// Current actually drawn by the most demanding frame, after limiting.
+    peak_drawn: f32,
+}
+
+impl PowerStats {
+    pub fn add(&mut self, draw: Draw) {
+        if draw.is_limited() {
+            self.limited += 1;
+        }
+        if self.frames == 0 || draw.requested > self.peak {
+            self.peak = draw.requested;
+            self.peak_frame = self.frames;
+        }
+        self.peak_drawn = self.peak_drawn.max(draw.drawn);
+        self.total += draw.drawn as f64;
+        self.frames += 1;
+    }
+
+    /// Average current actually drawn, in mA.
+    pub fn average(&self) -> f32 {
+        if self.frames == 0 {
+            0.
+        } else {
+            (self.total / self.frames as f64) as f32
+        }
+    }
+}
+
+impl fmt::Display for PowerStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "power: peak {:.2} A (frame {}), average {:.2} A",
+            self.peak / 1000.,
+            self.peak_frame,
+            self.average() / 1000.
+        )?;
+        if self.limited > 0 {
+            write!(
+                f,
+                ", {} of {} frames limited to at most {:.2} A",
+                self.limited,
+                self.frames,
+                self.peak_drawn / 1000.
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Limits frames on their way to an output, keeping stats on what it drew.
+pub struct LimitedOutput<O: Output> {
+    output: O,
+    model: PowerModel,
+    pub stats: PowerStats,
+}
+
+impl<O: Output> LimitedOutput<O> {
+    pub fn new(output: O, model: PowerModel) -> Self {
+        LimitedOutput {
+            output,
+            model,
+            stats: PowerStats::default(),
+        }
+    }
+}
+
+impl<O: Output> Output for LimitedOutput<O> {
+    fn send_frame(&mut self, frame: &[Color]) -> io::Result<()> {
+        let mut frame = frame.to_vec();
+        self.stats.add(self.model.limit(&mut frame));
+        self.output.send_frame(&frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let model = PowerModel::default();
+        let frame = [
+            Color::new(1., 1., 1., 1.),
+            Color::new(0.5, 0., 0., 1.),
+            Color::new(0., 0., 0., 1.),
+        ];
+        // 60 + 10 + 0, and 1 each idle
+        assert!((model.estimate(&frame) - 73.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn limiting() {
+        let model = PowerModel {
+            limit: Some(2. * 30. + 2.),
+            ..Default::default()
+        };
+        let white = Color::new(1., 1., 1., 1.);
+
+        // 122 mA to start with, the 120 mA of colour has to halve
+        let mut frame = [white, white];
+        let limited = model.limit(&mut frame);
+        assert!((limited.requested - 122.).abs() < 1e-4);
+        assert!((limited.drawn - 62.).abs() < 1e-3);
+        assert!((model.estimate(&frame) - 62.).abs() < 1e-3);
+        assert_eq!(frame[0], Color::new(0.5, 0.5, 0.5, 1.));
+
+        // Already under, left alone
+        let mut frame = [Color::new(0.2, 0., 0., 1.), Color::new(0., 0., 0., 1.)];
+        let unlimited = model.limit(&mut frame);
+        assert!(!unlimited.is_limited());
+        assert_eq!(frame[0], Color::new(0.2, 0., 0., 1.));
+
+        let mut stats = PowerStats::default();
+        stats.add(limited);
+        stats.add(unlimited);
+        assert!((stats.average() - 34.).abs() < 1e-3);
+        assert_eq!(
+            stats.to_string(),
+            "power: peak 0.12 A (frame 0), average 0.03 A, 1 of 2 frames limited to at most 0.06 A"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Rendering patterns on the tree to animated GIFs or PNG frames.
+//!
+//! Everything's drawn on the CPU, so this works on machines without a display
+//! or GPU, e.g. CI building previews of pattern changes.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+mod raster;
+
+pub use raster::Camera;
+use raster::Canvas;
+
+use crate::export::Progress;
+use crate::patterns::{FrameContext, Pattern};
+use crate::tree::Pixel;
+
+/// Everything about how to render, apart from what and where from.
+pub struct RenderOptions {
+    pub width: usize,
+    pub height: usize,
+    /// Maximum number of frames to render.
+    pub frame_limit: usize,
+    pub fps: u32,
+    /// Camera rotation speed around the tree.
+    pub rpm: f32,
+    /// LED size in tree units.
+    pub led_radius: f32,
+}
+
+/// Renders `pattern` to `filename`.
+///
+/// A `.gif` gets an animated GIF, a `.png` gets numbered frames alongside it,
+/// so `out.png` becomes `out_0000.png`, `out_0001.png` and so on.
+pub fn render_pattern(
+    tree: &[Pixel],
+    pattern: &mut dyn Pattern,
+    filename: &str,
+    camera: &Camera,
+    options: &RenderOptions,
+) -> io::Result<()> {
+    if options.width == 0 || options.height == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "image size must be at least 1x1",
+        ));
+    }
+    if options.fps == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "fps must be at least 1",
+        ));
+    }
+
+    let lowercase = filename.to_ascii_lowercase();
+    if lowercase.ends_with(".gif") {
+        let out = BufWriter::new(File::create(filename)?);
+        write_gif(out, tree, pattern, camera, options)
+    } else if lowercase.ends_with(".png") {
+        let stem = &filename[..filename.len() - ".png".len()];
+        write_pngs(stem, tree, pattern, camera, options)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: expected a .gif or .png file name", filename),
+        ))
+    }
+}
+
+/// Runs `pattern` for up to the frame limit, handing each rendered image to
+/// `write` as soon as it's drawn.
+fn for_each_image<F>(
+    tree: &[Pixel],
+    pattern: &mut dyn Pattern,
+    camera: &Camera,
+    options: &RenderOptions,
+    mut write: F,
+) -> io::Result<()>
+where
+    F: FnMut(usize, &Canvas) -> io::Result<()>,
+{
+    let rot_vel = std::f32::consts::PI * 2. * (options.rpm / 60.);
+    let mut canvas = Canvas::new(options.width, options.height);
+    let mut progress = Progress::new("rendered", options.frame_limit);
+
+    let mut i = 0;
+    while i < options.frame_limit {
+        let ctx = FrameContext::at_rate(i, options.fps);
+        let frame = match pattern.next_frame(&ctx) {
+            Some(frame) => frame,
+            None => break,
+        };
+
+        let camera = Camera {
+            yaw: camera.yaw + rot_vel * ctx.time,
+            ..*camera
+        };
+        canvas.draw_frame(&camera, tree, &frame, options.led_radius);
+        write(i, &canvas)?;
+
+        i += 1;
+        progress.update(i);
+    }
+
+    progress.finish(i);
+    Ok(())
+}
+
+/// Writes an endlessly looping animated GIF.
+///
+/// GIF frame delays are in hundredths of a second, so the frame rate is only
+/// approximate, e.g. 30 fps plays at 33.
+fn write_gif<W: io::Write>(
+    out: W,
+    tree: &[Pixel],
+    pattern: &mut dyn Pattern,
+    camera: &Camera,
+    options: &RenderOptions,
+) -> io::Result<()> {
+    let (width, height) = match (u16::try_from(options.width), u16::try_from(options.height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GIFs can be at most 65535 pixels across",
+            ))
+        }
+    };
+    let delay = ((100. / options.fps as f32).round() as u16).max(1);
+
+    let mut encoder = gif::Encoder::new(out, width, height, &[]).map_err(io::Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(io::Error::other)?;
+
+    for_each_image(tree, pattern, camera, options, |_, canvas| {
+        // Speed 10 quantizes a lot faster than the default, and still looks fine
+        let mut frame = gif::Frame::from_rgb_speed(width, height, &canvas.to_rgb(), 10);
+        frame.delay = delay;
+        encoder.write_frame(&frame).map_err(io::Error::other)
+    })
+}
+
+/// Writes each frame to its own `<stem>_NNNN.png`.
+fn write_pngs(
+    stem: &str,
+    tree: &[Pixel],
+    pattern: &mut dyn Pattern,
+    camera: &Camera,
+    options: &RenderOptions,
+) -> io::Result<()> {
+    for_each_image(tree, pattern, camera, options, |i, canvas| {
+        let out = BufWriter::new(File::create(format!("{}_{:04}.png", stem, i))?);
+        let mut encoder = png::Encoder::new(out, canvas.width() as u32, canvas.height() as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&canvas.to_rgb())?;
+        writer.finish()?;
+        Ok(())
+    })
+}
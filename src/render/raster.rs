@@ -0,0 +1,249 @@
+//! Software rasterizer for drawing a tree's LEDs as little spheres.
+//!
+//! Only ever draws spheres, so there's no need for triangles or a depth
+//! buffer: they're sorted back to front and painted over each other.
+
+use glam::{vec3, Vec3};
+
+use crate::colors::{channel_to_u8, Color};
+use crate::tree::{self, Pixel};
+
+/// Same grey the viewer clears to.
+const BACKGROUND: Color = Color::new(0.31, 0.31, 0.31, 1.);
+/// Smallest radius an LED is drawn at, in image pixels, so distant ones don't vanish.
+const MIN_RADIUS: f32 = 0.75;
+
+/// Keep pitch just shy of straight up/down so the up vector stays valid, as
+/// the viewer does.
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+/// Camera orbiting a target point, Z-up, like the viewer's.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub target: Vec3,
+    /// Angle around the Z axis in radians, 0 looks along -Y.
+    pub yaw: f32,
+    /// Angle above the XY plane in radians, kept within `MAX_PITCH`.
+    pub pitch: f32,
+    pub distance: f32,
+    /// Vertical field of view in radians.
+    pub fov: f32,
+}
+
+impl Camera {
+    /// Frames the whole tree the same way the viewer starts out.
+    pub fn framing(tree: &[Pixel]) -> Self {
+        let (min, max) = tree::bounds(tree);
+        Camera {
+            target: (min + max) / 2.,
+            yaw: 0.,
+            pitch: f32::atan2(1.5, 4.),
+            distance: (max - min).length().max(1.),
+            fov: 45f32.to_radians(),
+        }
+    }
+
+    fn position(&self) -> Vec3 {
+        let pitch = self.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+        self.target
+            + self.distance
+                * vec3(
+                    pitch.cos() * self.yaw.sin(),
+                    pitch.cos() * self.yaw.cos(),
+                    pitch.sin(),
+                )
+    }
+}
+
+/// Camera basis, ready to project points onto an image.
+struct Projection {
+    position: Vec3,
+    forward: Vec3,
+    right: Vec3,
+    up: Vec3,
+    /// Image pixels per unit at a depth of 1.
+    focal: f32,
+    centre_x: f32,
+    centre_y: f32,
+}
+
+impl Projection {
+    fn new(camera: &Camera, width: usize, height: usize) -> Self {
+        let position = camera.position();
+        let forward = (camera.target - position).normalize();
+        let right = forward.cross(vec3(0., 0., 1.)).normalize();
+        let up = right.cross(forward);
+        Projection {
+            position,
+            forward,
+            right,
+            up,
+            focal: height as f32 / 2. / (camera.fov / 2.).tan(),
+            centre_x: width as f32 / 2.,
+            centre_y: height as f32 / 2.,
+        }
+    }
+
+    /// Image x, y and depth of `point`, or `None` if it's behind the camera.
+    fn project(&self, point: Vec3) -> Option<(f32, f32, f32)> {
+        let offset = point - self.position;
+        let depth = offset.dot(self.forward);
+        if depth <= 1e-3 {
+            return None;
+        }
+        let scale = self.focal / depth;
+        Some((
+            self.centre_x + offset.dot(self.right) * scale,
+            self.centre_y - offset.dot(self.up) * scale,
+            depth,
+        ))
+    }
+}
+
+/// An RGB image being drawn into.
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    /// Colour per pixel, row by row from the top left.
+    pixels: Vec<Color>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: vec![BACKGROUND; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The image as packed 8-bit RGB.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|c| [channel_to_u8(c.r), channel_to_u8(c.g), channel_to_u8(c.b)])
+            .collect()
+    }
+
+    /// Clears the image and draws `frame` on `tree` as seen from `camera`.
+    ///
+    /// `radius` is the LED size in tree units.
+    pub fn draw_frame(&mut self, camera: &Camera, tree: &[Pixel], frame: &[Color], radius: f32) {
+        self.pixels.fill(BACKGROUND);
+
+        let projection = Projection::new(camera, self.width, self.height);
+        let mut spheres: Vec<(f32, f32, f32, Color)> = tree
+            .iter()
+            .zip(frame)
+            .filter_map(|(pixel, color)| {
+                let (x, y, depth) = projection.project(*pixel)?;
+                Some((x, y, depth, *color))
+            })
+            .collect();
+        // Furthest first, so nearer LEDs paint over them
+        spheres.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        for (x, y, depth, color) in spheres {
+            let screen_radius = (radius * projection.focal / depth).max(MIN_RADIUS);
+            self.draw_sphere(x, y, screen_radius, color);
+        }
+    }
+
+    fn draw_sphere(&mut self, centre_x: f32, centre_y: f32, radius: f32, color: Color) {
+        // Whole pixel range that could be touched, clipped to the image
+        let x0 = (centre_x - radius - 1.).floor().max(0.) as usize;
+        let y0 = (centre_y - radius - 1.).floor().max(0.) as usize;
+        let x1 = ((centre_x + radius + 1.).ceil().max(0.) as usize).min(self.width);
+        let y1 = ((centre_y + radius + 1.).ceil().max(0.) as usize).min(self.height);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let dx = x as f32 + 0.5 - centre_x;
+                let dy = y as f32 + 0.5 - centre_y;
+                let dist = (dx * dx + dy * dy).sqrt();
+                // Soft one pixel edge instead of jaggies
+                let coverage = (radius - dist + 0.5).clamp(0., 1.);
+                if coverage <= 0. {
+                    continue;
+                }
+
+                // Darken towards the rim a little so they read as spheres
+                let facing = (1. - (dist / radius).min(1.).powi(2)).sqrt();
+                let shade = 0.6 + 0.4 * facing;
+
+                let pixel = &mut self.pixels[y * self.width + x];
+                *pixel = pixel.lerp(color.scale(shade), coverage);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_camera() -> Camera {
+        Camera {
+            target: vec3(0., 0., 0.),
+            yaw: 0.,
+            pitch: 0.,
+            distance: 2.,
+            fov: 90f32.to_radians(),
+        }
+    }
+
+    #[test]
+    fn projection() {
+        let projection = Projection::new(&test_camera(), 100, 100);
+
+        // Looking along -Y from y = 2, with a 90 degree fov
+        let (x, y, depth) = projection.project(vec3(0., 0., 0.)).unwrap();
+        assert_eq!((x, y, depth), (50., 50., 2.));
+        let (x, y, _) = projection.project(vec3(0., 0., 2.)).unwrap();
+        assert!((x - 50.).abs() < 1e-4 && y.abs() < 1e-4);
+        let (x, _, _) = projection.project(vec3(-1., 0., 0.)).unwrap();
+        assert!((x - 75.).abs() < 1e-4);
+        assert!(projection.project(vec3(0., 3., 0.)).is_none());
+    }
+
+    #[test]
+    fn straight_down() {
+        for degrees in [90f32, -90., 135.] {
+            let camera = Camera {
+                pitch: degrees.to_radians(),
+                ..test_camera()
+            };
+            let projection = Projection::new(&camera, 100, 100);
+            let (x, y, depth) = projection.project(vec3(0., 0., 0.)).unwrap();
+            assert!(
+                (x - 50.).abs() < 1e-3 && (y - 50.).abs() < 1e-3,
+                "{}",
+                degrees
+            );
+            assert!((depth - 2.).abs() < 1e-3, "{}", degrees);
+        }
+    }
+
+    #[test]
+    fn nearest_led_wins() {
+        let red = Color::new(1., 0., 0., 1.);
+        let blue = Color::new(0., 0., 1., 1.);
+        let tree = [vec3(0., 0.5, 0.), vec3(0., -0.5, 0.)];
+
+        let mut canvas = Canvas::new(20, 20);
+        canvas.draw_frame(&test_camera(), &tree, &[red, blue], 0.2);
+
+        // Red is nearer, so it covers the middle over blue
+        let centre = canvas.pixels[10 * 20 + 10];
+        assert!(centre.r > 0.9 && centre.b == 0.);
+        assert_eq!(canvas.pixels[0], BACKGROUND);
+    }
+}
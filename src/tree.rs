@@ -6,52 +6,231 @@
 //! - Z is increasing upwards.
 //! - X and Y are scaled -1 to 1, origin is placed "at the bottom of the trunk"
 //! - Z is just same physical scale as X and Y, up to however tall the tree is.
+//!
+//! Trees are read from GIFT coordinate files: one `x,y,z` row per pixel, in
+//! string order. Real-world files vary a bit, so the importer also accepts:
+//! - a UTF-8 BOM
+//! - CRLF line endings and whitespace around values
+//! - blank lines and `#` comment lines
+//! - a header row (any first row with no numeric coordinates)
+//! - extra trailing columns, like a pixel index, which are ignored
 
+use std::fmt;
 use std::io::{Error, ErrorKind};
 
-use macroquad::math::{vec3, Vec3};
+use glam::{vec3, Vec3};
 
 pub type Pixel = Vec3;
 
-pub fn import_tree(path: &str) -> std::io::Result<Vec<Pixel>> {
-    let mut tree = std::fs::read_to_string(path)?;
+/// Everything that can go wrong importing a tree.
+///
+/// Line and column numbers are 1-based, to match what an editor shows.
+#[derive(Debug)]
+pub enum TreeError {
+    /// Couldn't read the file at all.
+    Io(Error),
+    /// A row ended before all three coordinates were read.
+    MissingValue { line: usize, column: usize },
+    /// A coordinate isn't a finite number.
+    InvalidValue {
+        line: usize,
+        column: usize,
+        value: String,
+    },
+    /// The file held no pixels.
+    Empty,
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TreeError::Io(err) => write!(f, "{}", err),
+            TreeError::MissingValue { line, column } => write!(
+                f,
+                "line {}, column {}: missing {} coordinate",
+                line,
+                column,
+                axis_name(*column)
+            ),
+            TreeError::InvalidValue {
+                line,
+                column,
+                value,
+            } => write!(
+                f,
+                "line {}, column {}: invalid {} coordinate \"{}\"",
+                line,
+                column,
+                axis_name(*column),
+                value
+            ),
+            TreeError::Empty => write!(f, "tree contains no pixels"),
+        }
+    }
+}
+
+impl std::error::Error for TreeError {}
+
+impl From<Error> for TreeError {
+    fn from(err: Error) -> Self {
+        TreeError::Io(err)
+    }
+}
+
+impl From<TreeError> for Error {
+    fn from(err: TreeError) -> Self {
+        match err {
+            TreeError::Io(err) => err,
+            err => Error::new(ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+fn axis_name(column: usize) -> &'static str {
+    match column {
+        1 => "x",
+        2 => "y",
+        _ => "z",
+    }
+}
+
+pub fn import_tree(path: &str) -> Result<Vec<Pixel>, TreeError> {
+    let tree = std::fs::read_to_string(path)?;
+    parse_tree(&tree)
+}
+
+/// Axis-aligned bounding box of a tree, as `(min, max)` corners.
+pub fn bounds(tree: &[Pixel]) -> (Pixel, Pixel) {
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    for pixel in tree {
+        min = min.min(*pixel);
+        max = max.max(*pixel);
+    }
+
+    // Keep an empty tree from producing infinities
+    if tree.is_empty() {
+        (Vec3::ZERO, Vec3::ZERO)
+    } else {
+        (min, max)
+    }
+}
 
+/// Parses the contents of a GIFT coordinate file.
+pub fn parse_tree(tree: &str) -> Result<Vec<Pixel>, TreeError> {
     // Strip UTF-8 BOM from beginning of string, if there
     // (Ugh why do I have to deal with this)
-    if tree.as_bytes()[0..3] == [0xEF, 0xBB, 0xBF] {
-        // TODO make this not bad
-        tree = String::from(&tree[3..tree.len()]);
-    }
+    let tree = tree.strip_prefix('\u{feff}').unwrap_or(tree);
 
     let mut pixels = Vec::new();
-    for line in tree.lines() {
-        let mut pix = line.split(',');
-
-        // I know a macro is the easy way but there must be a cleaner impl of this
-        // TODO remove unwraps
-        let x: f32 = match pix.next() {
-            Some(x) => str::parse(x.trim()).unwrap(),
-            None => {
-                return Err(Error::new(ErrorKind::InvalidData, "No x"));
-            }
-        };
+    let mut first_row = true;
+    for (i, line) in tree.lines().enumerate() {
+        let line_num = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
 
-        let y: f32 = match pix.next() {
-            Some(y) => str::parse(y).unwrap(),
-            None => {
-                return Err(Error::new(ErrorKind::InvalidData, "No y"));
-            }
-        };
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
 
-        let z: f32 = match pix.next() {
-            Some(z) => str::parse(z).unwrap(),
-            None => {
-                return Err(Error::new(ErrorKind::InvalidData, "No z"));
+        // A header is only allowed up front, and only if it has no numbers in
+        // it, so a typo in the first pixel still gets reported.
+        if first_row {
+            first_row = false;
+            if fields.iter().take(3).all(|f| f.parse::<f32>().is_err()) {
+                continue;
             }
-        };
+        }
 
-        pixels.push(vec3(x, y, z));
+        let mut coords = [0.; 3];
+        for (column, coord) in coords.iter_mut().enumerate() {
+            let column = column + 1;
+            let value = match fields.get(column - 1) {
+                Some(value) if !value.is_empty() => *value,
+                _ => {
+                    return Err(TreeError::MissingValue {
+                        line: line_num,
+                        column,
+                    })
+                }
+            };
+
+            *coord = match value.parse::<f32>() {
+                Ok(v) if v.is_finite() => v,
+                _ => {
+                    return Err(TreeError::InvalidValue {
+                        line: line_num,
+                        column,
+                        value: String::from(value),
+                    })
+                }
+            };
+        }
+
+        pixels.push(vec3(coords[0], coords[1], coords[2]));
+    }
+
+    if pixels.is_empty() {
+        return Err(TreeError::Empty);
     }
 
     Ok(pixels)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_rows() {
+        let tree = parse_tree("0.1,0.2,0.3\n-1,1,2.5\n").unwrap();
+        assert_eq!(tree, vec![vec3(0.1, 0.2, 0.3), vec3(-1., 1., 2.5)]);
+    }
+
+    #[test]
+    fn real_world_variants() {
+        let file = "\u{feff}X,Y,Z,index\r\n\
+                    # exported from the scanner\r\n\
+                    \r\n\
+                    \t0.1 , 0.2,0.3 ,0\r\n\
+                    -1,  1 , 2.5, 1  \r\n";
+        let tree = parse_tree(file).unwrap();
+        assert_eq!(tree, vec![vec3(0.1, 0.2, 0.3), vec3(-1., 1., 2.5)]);
+    }
+
+    #[test]
+    fn empty_file() {
+        assert!(matches!(parse_tree(""), Err(TreeError::Empty)));
+        assert!(matches!(parse_tree("\u{feff}"), Err(TreeError::Empty)));
+        assert!(matches!(parse_tree("x,y,z\n\n"), Err(TreeError::Empty)));
+    }
+
+    #[test]
+    fn errors_name_line_and_column() {
+        match parse_tree("0,0,0\n0,abc,0\n") {
+            Err(TreeError::InvalidValue {
+                line: 2,
+                column: 2,
+                value,
+            }) => assert_eq!(value, "abc"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        assert!(matches!(
+            parse_tree("0,0,0\n\n1,1\n"),
+            Err(TreeError::MissingValue { line: 3, column: 3 })
+        ));
+        assert!(matches!(
+            parse_tree("0,,0\n"),
+            Err(TreeError::MissingValue { line: 1, column: 2 })
+        ));
+        assert!(matches!(
+            parse_tree("0,0,0\nx,y,z\n"),
+            Err(TreeError::InvalidValue {
+                line: 2,
+                column: 1,
+                ..
+            })
+        ));
+    }
+}
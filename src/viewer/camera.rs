@@ -0,0 +1,126 @@
+//! Mouse-driven orbit camera.
+//!
+//! - Left drag orbits around the target
+//! - Right drag pans the target
+//! - Scroll zooms
+//! - `A` toggles auto-rotate, `C` resets the view
+
+use macroquad::prelude::*;
+
+use crate::tree::{self, Pixel};
+
+/// Radians per pixel of mouse drag.
+const ORBIT_SENSITIVITY: f32 = 0.01;
+/// Zoom factor per scroll notch.
+const ZOOM_STEP: f32 = 0.9;
+/// Keep pitch just shy of straight up/down so the up vector stays valid.
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+/// Camera orbiting a target point, Z-up.
+pub struct OrbitCamera {
+    target: Vec3,
+    /// Angle around the Z axis, 0 looks along -Y.
+    yaw: f32,
+    /// Angle above the XY plane.
+    pitch: f32,
+    distance: f32,
+
+    /// Auto-rotate speed in radians/s.
+    rot_vel: f32,
+    auto_rotate: bool,
+
+    // Defaults to reset back to
+    home_target: Vec3,
+    home_pitch: f32,
+    home_distance: f32,
+
+    last_mouse: Vec2,
+}
+
+impl OrbitCamera {
+    /// Frames the whole tree, looking at the centre of its bounding box.
+    pub fn new(tree: &[Pixel], rpm: u32) -> Self {
+        let (min, max) = tree::bounds(tree);
+        let home_target = (min + max) / 2.;
+        // Works out to roughly the old fixed 4-unit orbit on Matt's tree
+        let home_distance = (max - min).length().max(1.);
+        let home_pitch = f32::atan2(1.5, 4.);
+
+        let (mouse_x, mouse_y) = mouse_position();
+        OrbitCamera {
+            target: home_target,
+            yaw: 0.,
+            pitch: home_pitch,
+            distance: home_distance,
+            rot_vel: std::f32::consts::PI * 2. * (rpm as f32 / 60.),
+            auto_rotate: rpm > 0,
+            home_target,
+            home_pitch,
+            home_distance,
+            last_mouse: vec2(mouse_x, mouse_y),
+        }
+    }
+
+    /// Puts the camera back where it started, keeping the current yaw.
+    pub fn reset(&mut self) {
+        self.target = self.home_target;
+        self.pitch = self.home_pitch;
+        self.distance = self.home_distance;
+    }
+
+    fn position(&self) -> Vec3 {
+        self.target
+            + self.distance
+                * vec3(
+                    self.pitch.cos() * self.yaw.sin(),
+                    self.pitch.cos() * self.yaw.cos(),
+                    self.pitch.sin(),
+                )
+    }
+
+    /// Applies mouse/keyboard input and auto-rotation for this frame.
+    pub fn update(&mut self, dt: f32) {
+        let (mouse_x, mouse_y) = mouse_position();
+        let mouse = vec2(mouse_x, mouse_y);
+        let delta = mouse - self.last_mouse;
+        self.last_mouse = mouse;
+
+        if is_key_pressed(KeyCode::A) {
+            self.auto_rotate = !self.auto_rotate;
+        }
+        if is_key_pressed(KeyCode::C) {
+            self.reset();
+        }
+
+        if is_mouse_button_down(MouseButton::Left) {
+            self.yaw -= delta.x * ORBIT_SENSITIVITY;
+            self.pitch = (self.pitch + delta.y * ORBIT_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+        } else if self.auto_rotate {
+            self.yaw += dt * self.rot_vel;
+        }
+
+        if is_mouse_button_down(MouseButton::Right) {
+            let forward = (self.target - self.position()).normalize();
+            let right = forward.cross(vec3(0., 0., 1.)).normalize();
+            let up = right.cross(forward);
+            // Scale so the tree roughly follows the cursor at any zoom
+            let scale = self.distance / screen_height();
+            self.target += (up * delta.y - right * delta.x) * scale;
+        }
+
+        // Wheel units vary by platform, only the direction is reliable
+        let (_, wheel) = mouse_wheel();
+        if wheel != 0. {
+            self.distance *= ZOOM_STEP.powf(wheel.signum());
+        }
+    }
+
+    pub fn camera(&self) -> Camera3D {
+        Camera3D {
+            position: self.position(),
+            target: self.target,
+            up: vec3(0., 0., 1.),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Interactive 3D viewer for running patterns on a tree.
+
+use macroquad::prelude::*;
+
+mod camera;
+mod player;
+
+use crate::colors;
+use crate::output::{OpenOutput, Output};
+use crate::power::PowerModel;
+use crate::tree;
+
+pub use player::PatternFactory;
+
+impl From<colors::Color> for Color {
+    fn from(color: colors::Color) -> Self {
+        Color::new(color.r, color.g, color.b, color.a)
+    }
+}
+
+// TODO assure pixels/frame line up
+fn render_frame(tree: &[tree::Pixel], frame: &[colors::Color]) {
+    for (pixel, color) in tree.iter().zip(frame) {
+        let location = vec3(pixel.x, pixel.y, pixel.z);
+        draw_sphere(location, 0.01, None, (*color).into());
+    }
+}
+
+/// Runs the viewer forever, looping whatever `factory` produces.
+///
+/// `name` is only used for display. Every new frame is sent to `outputs`, which
+/// each correct and limit it themselves, any that fail are reported and dropped
+/// so the viewer keeps going. The tree on screen is limited to `power` as it
+/// comes from the pattern, without any output's correction.
+pub async fn render_loop(
+    tree: Vec<tree::Pixel>,
+    name: String,
+    factory: PatternFactory,
+    rpm: u32,
+    fps: u32,
+    power: PowerModel,
+    mut outputs: Vec<OpenOutput>,
+) {
+    let mut camera = camera::OrbitCamera::new(&tree, rpm);
+    let mut player = player::Player::new(&tree, name, factory, fps);
+    let mut show_hud = true;
+    let mut last_sent = None;
+    let mut frame = Vec::new();
+    let mut draw = power.limit(&mut frame);
+
+    loop {
+        // Set up basic scene
+        clear_background(DARKGRAY);
+
+        let dt = get_frame_time();
+        if is_key_pressed(KeyCode::H) {
+            show_hud = !show_hud;
+        }
+        player.update(&tree, dt);
+
+        if last_sent != Some(player.frames_shown()) {
+            last_sent = Some(player.frames_shown());
+            frame = player.frame().to_vec();
+            outputs.retain_mut(|output| match output.send_frame(&frame) {
+                Ok(()) => true,
+                Err(err) => {
+                    eprintln!("output stopped: {}", err);
+                    false
+                }
+            });
+            draw = power.limit(&mut frame);
+        }
+
+        // Set up camera
+        camera.update(dt);
+        set_camera(&camera.camera());
+
+        // Draw pixels
+        render_frame(&tree, &frame);
+
+        set_default_camera();
+        if show_hud {
+            player.draw_hud(tree.len(), draw);
+        }
+        next_frame().await;
+    }
+}
@@ -0,0 +1,170 @@
+//! Pattern playback controls.
+//!
+//! - `Space` pauses/resumes
+//! - `Right` steps a single frame (and pauses)
+//! - `Up`/`Down` doubles/halves playback speed
+//! - `R` restarts the pattern from scratch
+
+use macroquad::prelude::*;
+
+use crate::colors;
+use crate::patterns::{FrameContext, Pattern};
+use crate::power::Draw;
+use crate::tree::Pixel;
+
+const MIN_SPEED: f32 = 1. / 16.;
+const MAX_SPEED: f32 = 16.;
+
+/// Creates a fresh pattern to play, used again whenever playback restarts.
+pub type PatternFactory = Box<dyn Fn(&[Pixel]) -> Box<dyn Pattern>>;
+
+/// Steps a pattern at a fixed frame rate, scaled by a playback speed.
+pub struct Player {
+    name: String,
+    factory: PatternFactory,
+    pattern: Box<dyn Pattern>,
+    frame: Vec<colors::Color>,
+    /// Index of the frame currently shown.
+    index: usize,
+    /// Frames shown so far, counting across restarts.
+    frames_shown: u64,
+
+    fps: u32,
+    speed: f32,
+    paused: bool,
+    /// Playback time not yet consumed by a frame, in seconds.
+    backlog: f32,
+
+    // Measured frame rate, counted over roughly a second
+    actual_fps: f32,
+    window_start: f64,
+    window_frames: u32,
+}
+
+impl Player {
+    pub fn new(tree: &[Pixel], name: String, factory: PatternFactory, fps: u32) -> Self {
+        let mut pattern = factory(tree);
+        let frame = pattern
+            .next_frame(&FrameContext::at_rate(0, fps))
+            .unwrap_or_default();
+        Player {
+            name,
+            factory,
+            pattern,
+            frame,
+            index: 0,
+            frames_shown: 0,
+            fps,
+            speed: 1.,
+            paused: false,
+            backlog: 0.,
+            actual_fps: 0.,
+            window_start: get_time(),
+            window_frames: 0,
+        }
+    }
+
+    /// Re-creates the pattern and shows its first frame.
+    pub fn restart(&mut self, tree: &[Pixel]) {
+        self.pattern = (self.factory)(tree);
+        self.frame = self
+            .pattern
+            .next_frame(&FrameContext::at_rate(0, self.fps))
+            .unwrap_or_default();
+        self.index = 0;
+        self.frames_shown += 1;
+        self.backlog = 0.;
+    }
+
+    /// Moves on a frame, looping back to the start when the pattern ends.
+    fn step(&mut self, tree: &[Pixel]) {
+        // Patterns always see a steady frame rate, playback speed only
+        // changes how often they're stepped
+        match self
+            .pattern
+            .next_frame(&FrameContext::at_rate(self.index + 1, self.fps))
+        {
+            Some(frame) => {
+                self.frame = frame;
+                self.index += 1;
+                self.frames_shown += 1;
+            }
+            None => self.restart(tree),
+        }
+        self.window_frames += 1;
+    }
+
+    /// Applies keyboard input and advances playback by `dt` seconds.
+    pub fn update(&mut self, tree: &[Pixel], dt: f32) {
+        if is_key_pressed(KeyCode::Space) {
+            self.paused = !self.paused;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            self.speed = (self.speed * 2.).min(MAX_SPEED);
+        }
+        if is_key_pressed(KeyCode::Down) {
+            self.speed = (self.speed / 2.).max(MIN_SPEED);
+        }
+        if is_key_pressed(KeyCode::R) {
+            self.restart(tree);
+        }
+        if is_key_pressed(KeyCode::Right) {
+            self.paused = true;
+            self.step(tree);
+        }
+
+        if !self.paused {
+            let frame_period = 1. / self.fps as f32;
+            // Don't try to catch up after a stall (window drag etc.), just drop time
+            self.backlog += dt.min(0.25) * self.speed;
+            while self.backlog >= frame_period {
+                self.backlog -= frame_period;
+                self.step(tree);
+            }
+        }
+
+        let now = get_time();
+        if now - self.window_start >= 1. {
+            self.actual_fps = self.window_frames as f32 / (now - self.window_start) as f32;
+            self.window_start = now;
+            self.window_frames = 0;
+        }
+    }
+
+    pub fn frame(&self) -> &[colors::Color] {
+        &self.frame
+    }
+
+    /// Goes up every time a new frame is shown.
+    pub fn frames_shown(&self) -> u64 {
+        self.frames_shown
+    }
+
+    /// Draws the status overlay in screen space, `power` being what the frame
+    /// shown draws.
+    pub fn draw_hud(&self, num_pixels: usize, power: Draw) {
+        let limited = if power.is_limited() {
+            format!(" (limited from {:.2} A)", power.requested / 1000.)
+        } else {
+            String::new()
+        };
+        let state = if self.paused { "  PAUSED" } else { "" };
+        let lines = [
+            format!("pattern: {}{}", self.name, state),
+            format!("frame: {}", self.index),
+            format!(
+                "fps: {:.1} target, {:.1} actual (x{})",
+                self.fps as f32 * self.speed,
+                self.actual_fps,
+                self.speed
+            ),
+            format!("pixels: {}", num_pixels),
+            format!("power: {:.2} A{}", power.drawn / 1000., limited),
+            String::from("space pause, right step, up/down speed, r restart, h hide"),
+        ];
+
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(line, 10., 20. + 20. * i as f32, 20., WHITE);
+        }
+    }
+}